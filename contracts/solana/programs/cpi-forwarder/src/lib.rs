@@ -0,0 +1,48 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program::invoke,
+};
+
+declare_id!("34RJVwbDPQHiXNFravT1mEL2VEdorzMKhV7QZ13dpJim");
+
+// Only used by the tests to reach twoside through CPI, forwarding to itself
+// first puts an intermediate program between the top level one and twoside
+#[program]
+pub mod cpi_forwarder {
+    use super::*;
+
+    pub fn forward<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Forward<'info>>,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        // signer and writable privileges are passed on as they were received
+        let accounts = ctx
+            .remaining_accounts
+            .iter()
+            .map(|account| AccountMeta {
+                pubkey: account.key(),
+                is_signer: account.is_signer,
+                is_writable: account.is_writable,
+            })
+            .collect();
+        let instruction = Instruction {
+            program_id: ctx.accounts.target_program.key(),
+            accounts,
+            data,
+        };
+
+        let mut account_infos = ctx.remaining_accounts.to_vec();
+        account_infos.push(ctx.accounts.target_program.to_account_info());
+        invoke(&instruction, &account_infos)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct Forward<'info> {
+    /// CHECK: any executable, the instruction data is relayed untouched
+    #[account(executable)]
+    pub target_program: UncheckedAccount<'info>,
+}
@@ -1,9 +1,34 @@
 use anchor_lang::prelude::*;
 
+// disambiguates from the standalone borsh dependency for enum derives
+use anchor_lang::prelude::borsh;
+use anchor_lang::{
+    solana_program::{
+        instruction::{get_stack_height, TRANSACTION_LEVEL_STACK_HEIGHT},
+        program_pack::Pack,
+        sysvar::instructions::get_instruction_relative,
+    },
+    system_program::{self, Allocate, Assign, CreateAccount, Transfer},
+};
+
 use anchor_spl::{
     associated_token::AssociatedToken,
-    token::{
-        self, mint_to, transfer_checked, Burn, Mint, MintTo, Token, TokenAccount, TransferChecked,
+    token_2022::spl_token_2022::{
+        self,
+        extension::{
+            default_account_state::DefaultAccountState, transfer_fee::TransferFeeConfig,
+            transfer_hook::TransferHook, BaseStateWithExtensions, ExtensionType,
+            StateWithExtensions,
+        },
+        state::AccountState,
+    },
+    token_interface::{
+        self, initialize_mint2, metadata_pointer_initialize, mint_to,
+        spl_pod::optional_keys::OptionalNonZeroPubkey,
+        spl_token_metadata_interface::state::TokenMetadata, token_metadata_initialize,
+        token_metadata_update_authority, transfer_checked, Burn, InitializeMint2,
+        MetadataPointerInitialize, Mint, MintTo, TokenAccount, TokenInterface,
+        TokenMetadataInitialize, TokenMetadataUpdateAuthority, TransferChecked,
     },
 };
 
@@ -19,6 +44,20 @@ use mpl_token_metadata::{
 
 declare_id!("8Z48mrWoffcR3FoqAbcgE7fgRXBYV4zqLD5cMCD44d7f");
 
+// Builds the EventEmitter for a handler, the accounts struct needs
+// #[cfg_attr(feature = "event-cpi", event_cpi)] for the self-CPI accounts
+macro_rules! event_emitter {
+    ($ctx:expr) => {
+        EventEmitter {
+            #[cfg(feature = "event-cpi")]
+            authority: $ctx.accounts.event_authority.to_account_info(),
+            #[cfg(feature = "event-cpi")]
+            authority_bump: $ctx.bumps.event_authority,
+            _info: std::marker::PhantomData,
+        }
+    };
+}
+
 #[program]
 pub mod twoside {
     use super::*;
@@ -41,50 +80,430 @@ pub mod twoside {
             !global_info.is_initialized,
             TwosideErrorCodes::ProgramInitialized
         );
+        global_info.version = GLOBAL_INFO_VERSION;
         global_info.is_initialized = true;
-        global_info.developer_wallet = developer_wallet;
-        global_info.founder_wallet = founder_wallet;
-        global_info.fee_percentage = 5;
-        global_info.fee_percentage_divider = 1000;
-        global_info.min_fee_for_distribution = 2;
-        global_info.min_fee = 2;
-        global_info.developer_fee_share = 50;
-        global_info.founder_fee_share = 50;
+        global_info.fee_recipients = vec![
+            FeeRecipient {
+                wallet: developer_wallet,
+                weight_bps: 5_000,
+            },
+            FeeRecipient {
+                wallet: founder_wallet,
+                weight_bps: 5_000,
+            },
+        ];
+        global_info.lock_fee = FeeConfig::DEFAULT;
+        global_info.unlock_fee = FeeConfig::DEFAULT;
+        global_info.admin = ctx.accounts.signer.key();
+        global_info.permissionless_registration = true;
+        global_info.token_list_mode = TokenListMode::Open;
+        global_info.fee_remainder_index = 1;
+        global_info.referral_fee_bps = 0;
         Ok(())
     }
 
-    pub fn lock(ctx: Context<Lock>, amount: u64) -> Result<()> {
+    pub fn update_global_config(
+        ctx: Context<UpdateGlobalConfig>,
+        config: GlobalConfig,
+    ) -> Result<()> {
+        let events = event_emitter!(ctx);
+        validate_global_config(&config)?;
+
+        let global_info = &mut ctx.accounts.global_info;
+        let old_config = global_info.config();
+        global_info.set_config(&config);
+
+        let clock = Clock::get()?;
+        events.emit(&GlobalConfigUpdated {
+            admin: ctx.accounts.admin.key(),
+            old_config,
+            new_config: config,
+            timestamp: clock.unix_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    pub fn propose_admin(ctx: Context<ProposeAdmin>, new_admin: Pubkey) -> Result<()> {
+        let events = event_emitter!(ctx);
+        require!(
+            new_admin != Pubkey::default(),
+            TwosideErrorCodes::InvalidPubkey
+        );
+
+        let global_info = &mut ctx.accounts.global_info;
+        global_info.pending_admin = new_admin;
+
+        let clock = Clock::get()?;
+        events.emit(&AdminTransferProposed {
+            admin: global_info.admin,
+            pending_admin: new_admin,
+            timestamp: clock.unix_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    pub fn accept_admin(ctx: Context<AcceptAdmin>) -> Result<()> {
+        let events = event_emitter!(ctx);
+        let global_info = &mut ctx.accounts.global_info;
+        let old_admin = global_info.admin;
+        global_info.admin = ctx.accounts.pending_admin.key();
+        global_info.pending_admin = Pubkey::default();
+
+        let clock = Clock::get()?;
+        events.emit(&AdminTransferAccepted {
+            old_admin,
+            new_admin: global_info.admin,
+            timestamp: clock.unix_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    pub fn cancel_admin_transfer(ctx: Context<CancelAdminTransfer>) -> Result<()> {
+        let events = event_emitter!(ctx);
+        let global_info = &mut ctx.accounts.global_info;
+        require!(
+            global_info.pending_admin != Pubkey::default(),
+            TwosideErrorCodes::NoPendingAdmin
+        );
+        let cancelled_admin = global_info.pending_admin;
+        global_info.pending_admin = Pubkey::default();
+
+        let clock = Clock::get()?;
+        events.emit(&AdminTransferCancelled {
+            admin: global_info.admin,
+            cancelled_admin,
+            timestamp: clock.unix_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    pub fn set_global_pause(ctx: Context<SetGlobalPause>, paused: u8) -> Result<()> {
+        let events = event_emitter!(ctx);
+        require!(
+            paused & !PAUSE_ALL == 0,
+            TwosideErrorCodes::InvalidPauseFlags
+        );
+
+        let global_info = &mut ctx.accounts.global_info;
+        global_info.paused = paused;
+
+        let clock = Clock::get()?;
+        events.emit(&GlobalPauseUpdated {
+            admin: ctx.accounts.admin.key(),
+            paused,
+            timestamp: clock.unix_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    pub fn set_token_pause(ctx: Context<SetTokenPause>, paused: u8) -> Result<()> {
+        let events = event_emitter!(ctx);
+        require!(
+            paused & !PAUSE_ALL == 0,
+            TwosideErrorCodes::InvalidPauseFlags
+        );
+
+        let token_info = &mut ctx.accounts.token_info;
+        token_info.paused = paused;
+
+        let clock = Clock::get()?;
+        events.emit(&TokenPauseUpdated {
+            admin: ctx.accounts.admin.key(),
+            token: token_info.original_mint,
+            paused,
+            timestamp: clock.unix_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    pub fn set_token_fee_override(
+        ctx: Context<SetTokenFeeOverride>,
+        lock_fee_override: Option<FeeConfig>,
+        unlock_fee_override: Option<FeeConfig>,
+    ) -> Result<()> {
+        let events = event_emitter!(ctx);
+        for fee_config in lock_fee_override.iter().chain(unlock_fee_override.iter()) {
+            validate_fee_config(fee_config)?;
+        }
+
+        let token_info = &mut ctx.accounts.token_info;
+        token_info.lock_fee_override = lock_fee_override;
+        token_info.unlock_fee_override = unlock_fee_override;
+
+        let clock = Clock::get()?;
+        events.emit(&TokenFeeOverrideUpdated {
+            admin: ctx.accounts.admin.key(),
+            token: token_info.original_mint,
+            lock_fee_override,
+            unlock_fee_override,
+            timestamp: clock.unix_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    pub fn set_token_sol_fee(ctx: Context<SetTokenSolFee>, sol_fee: Option<SolFee>) -> Result<()> {
+        let events = event_emitter!(ctx);
+        if let Some(sol_fee) = &sol_fee {
+            validate_sol_fee(sol_fee)?;
+        }
+        let token_info = &mut ctx.accounts.token_info;
+        token_info.sol_fee = sol_fee;
+
+        let clock = Clock::get()?;
+        events.emit(&TokenSolFeeUpdated {
+            admin: ctx.accounts.admin.key(),
+            token: token_info.original_mint,
+            sol_fee,
+            timestamp: clock.unix_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    pub fn migrate_global_info(ctx: Context<MigrateGlobalInfo>) -> Result<()> {
+        let events = event_emitter!(ctx);
         let system_program = &ctx.accounts.system_program;
-        let token_program = &ctx.accounts.token_program;
+        let signer = &ctx.accounts.signer;
+        let global_info_acc = &ctx.accounts.global_info;
+
+        // the stored version picks the layout to convert from
+        let global_info = match read_account_version(global_info_acc, GlobalInfo::DISCRIMINATOR)? {
+            0 => return Err(ProgramError::UninitializedAccount.into()),
+            1 => {
+                let legacy: GlobalInfoV1 = read_legacy_account(
+                    global_info_acc,
+                    GlobalInfo::DISCRIMINATOR,
+                    GlobalInfoV1::LEN,
+                )?;
+                // the legacy layout has no admin, the upgrade authority that
+                // signs the migration takes the role as in initialize_program
+                GlobalInfo::from_v1(legacy, signer.key())?
+            }
+            GLOBAL_INFO_VERSION => return err!(TwosideErrorCodes::AccountAlreadyMigrated),
+            _ => return Err(ProgramError::InvalidAccountData.into()),
+        };
+        validate_global_config(&global_info.config())?;
+
+        resize_account(global_info_acc, 8 + GlobalInfo::LEN, signer, system_program)?;
+        global_info.try_serialize(&mut &mut global_info_acc.data.borrow_mut()[..])?;
+
+        let clock = Clock::get()?;
+        events.emit(&GlobalInfoMigrated {
+            admin: signer.key(),
+            version: GLOBAL_INFO_VERSION,
+            timestamp: clock.unix_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    pub fn migrate_token_info(ctx: Context<MigrateTokenInfo>) -> Result<()> {
+        let events = event_emitter!(ctx);
+        let system_program = &ctx.accounts.system_program;
+        let signer = &ctx.accounts.signer;
+        let token_mint = &ctx.accounts.token_mint;
+        let token_info_acc = &ctx.accounts.token_info;
+        let vault_ata = &ctx.accounts.vault_ata;
+        let fee_vault = &mut ctx.accounts.fee_vault;
+
+        // the stored version picks the layout to convert from
+        let legacy: TokenInfoV1 =
+            match read_account_version(token_info_acc, TokenInfo::DISCRIMINATOR)? {
+                0 => return Err(ProgramError::UninitializedAccount.into()),
+                1 => {
+                    read_legacy_account(token_info_acc, TokenInfo::DISCRIMINATOR, TokenInfoV1::LEN)?
+                }
+                TOKEN_INFO_VERSION => return err!(TwosideErrorCodes::AccountAlreadyMigrated),
+                _ => return Err(ProgramError::InvalidAccountData.into()),
+            };
+
+        resize_account(token_info_acc, 8 + TokenInfo::LEN, signer, system_program)?;
+        let clock = Clock::get()?;
+        let token_info = TokenInfo::from_v1(legacy, vault_ata.amount, clock.unix_timestamp);
+        token_info.try_serialize(&mut &mut token_info_acc.data.borrow_mut()[..])?;
+
+        // legacy tokens predate fee vaults, lock and unlock need one
+        fee_vault.original_mint = token_mint.key();
+
+        events.emit(&TokenInfoMigrated {
+            signer: signer.key(),
+            token: token_mint.key(),
+            version: TOKEN_INFO_VERSION,
+            timestamp: clock.unix_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    pub fn set_token_label(
+        ctx: Context<SetTokenLabel>,
+        name: String,
+        symbol: String,
+        uri: String,
+    ) -> Result<()> {
+        let events = event_emitter!(ctx);
+        require!(
+            !name.is_empty() && name.len() <= MAX_LABEL_NAME_LENGTH,
+            TwosideErrorCodes::InvalidTokenLabel
+        );
+        require!(
+            !symbol.is_empty() && symbol.len() <= MAX_LABEL_SYMBOL_LENGTH,
+            TwosideErrorCodes::InvalidTokenLabel
+        );
+        require!(
+            uri.len() <= MAX_LABEL_URI_LENGTH,
+            TwosideErrorCodes::InvalidTokenLabel
+        );
+
+        let token_label = &mut ctx.accounts.token_label;
+        token_label.original_mint = ctx.accounts.token_mint.key();
+        token_label.name = name;
+        token_label.symbol = symbol;
+        token_label.uri = uri;
+
+        let clock = Clock::get()?;
+        events.emit(&TokenLabelSet {
+            admin: ctx.accounts.admin.key(),
+            token: token_label.original_mint,
+            name: token_label.name.clone(),
+            symbol: token_label.symbol.clone(),
+            timestamp: clock.unix_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    pub fn set_token_list_status(
+        ctx: Context<SetTokenListStatus>,
+        status: TokenListStatus,
+    ) -> Result<()> {
+        let events = event_emitter!(ctx);
+        let token_list_entry = &mut ctx.accounts.token_list_entry;
+        token_list_entry.original_mint = ctx.accounts.token_mint.key();
+        token_list_entry.status = status;
+
+        let clock = Clock::get()?;
+        events.emit(&TokenListStatusUpdated {
+            admin: ctx.accounts.admin.key(),
+            token: token_list_entry.original_mint,
+            status,
+            timestamp: clock.unix_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    pub fn set_fee_exemption(
+        ctx: Context<SetFeeExemption>,
+        subject: Pubkey,
+        exempt: bool,
+    ) -> Result<()> {
+        let events = event_emitter!(ctx);
+        let fee_exemption = &mut ctx.accounts.fee_exemption;
+        fee_exemption.subject = subject;
+        fee_exemption.exempt = exempt;
+
+        let clock = Clock::get()?;
+        events.emit(&FeeExemptionUpdated {
+            admin: ctx.accounts.admin.key(),
+            subject,
+            exempt,
+            timestamp: clock.unix_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    pub fn set_referrer_status(
+        ctx: Context<SetReferrerStatus>,
+        referrer: Pubkey,
+        allowed: bool,
+    ) -> Result<()> {
+        let events = event_emitter!(ctx);
+        let referrer_entry = &mut ctx.accounts.referrer_entry;
+        referrer_entry.referrer = referrer;
+        referrer_entry.allowed = allowed;
+
+        let clock = Clock::get()?;
+        events.emit(&ReferrerStatusUpdated {
+            admin: ctx.accounts.admin.key(),
+            referrer,
+            allowed,
+            timestamp: clock.unix_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    pub fn set_fee_tiers(
+        ctx: Context<SetFeeTiers>,
+        basis: FeeTierBasis,
+        tiers: Vec<FeeTier>,
+    ) -> Result<()> {
+        let events = event_emitter!(ctx);
+        let global_info = &ctx.accounts.global_info;
+        let token_info = &ctx.accounts.token_info;
+        let base_fee_bps = resolve_fee_config(global_info, token_info, FeeLeg::Lock)
+            .fee_bps
+            .max(resolve_fee_config(global_info, token_info, FeeLeg::Unlock).fee_bps);
+        validate_fee_tiers(&tiers, base_fee_bps)?;
+
+        let fee_tiers = &mut ctx.accounts.fee_tiers;
+        fee_tiers.original_mint = ctx.accounts.token_mint.key();
+        fee_tiers.basis = basis;
+        fee_tiers.tiers = tiers;
+
+        let clock = Clock::get()?;
+        events.emit(&FeeTiersUpdated {
+            admin: ctx.accounts.admin.key(),
+            token: fee_tiers.original_mint,
+            basis,
+            tiers: fee_tiers.tiers.clone(),
+            timestamp: clock.unix_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    pub fn register_token(ctx: Context<RegisterToken>) -> Result<()> {
+        let events = event_emitter!(ctx);
+        let system_program = &ctx.accounts.system_program;
+        let derivative_token_program = &ctx.accounts.derivative_token_program;
         let mpl_token_metadata_program = &ctx.accounts.mpl_token_metadata_program;
 
         let token_mint = &ctx.accounts.token_mint;
         let derivative_mint = &ctx.accounts.derivative_mint;
         let derivative_authority = &ctx.accounts.derivative_authority;
         let token_info = &mut ctx.accounts.token_info;
-        let vault_authority = &ctx.accounts.vault_authority;
-        let vault_ata = &ctx.accounts.vault_ata;
+        let fee_vault = &mut ctx.accounts.fee_vault;
         let token_metadata_acc = &ctx.accounts.token_metadata;
+        let token_label_acc = &ctx.accounts.token_label;
+        let token_list_entry_acc = &ctx.accounts.token_list_entry;
         let derivative_metadata_acc = &ctx.accounts.derivative_metadata;
 
         let global_info = &ctx.accounts.global_info;
-        let founder_ata = &ctx.accounts.founder_ata;
-        let developer_ata = &ctx.accounts.developer_ata;
-
         let signer = &ctx.accounts.signer;
-        let signer_token_ata = &ctx.accounts.signer_token_ata;
-        let signer_derivative_ata = &ctx.accounts.signer_derivative_ata;
-
-        require_keys_eq!(
-            mpl_token_metadata_program.key(),
-            metaplex_id,
-            TwosideErrorCodes::InvalidMetaplexProgram
-        );
 
-        require!(amount != 0, TwosideErrorCodes::ZeroAmountValue);
+        if !global_info.permissionless_registration {
+            require_keys_eq!(
+                signer.key(),
+                global_info.admin,
+                TwosideErrorCodes::Unauthorized
+            );
+        }
+        check_token_list(global_info, token_list_entry_acc)?;
+        validate_mint_extensions(&token_mint.to_account_info())?;
 
         let clock = Clock::get()?;
         let current_timestamp = clock.unix_timestamp;
+        token_info.created_at = current_timestamp;
 
         let mint_key = token_mint.key();
         let derivative_authority_seeds: &[&[u8]] = &[
@@ -94,114 +513,262 @@ pub mod twoside {
         ];
         let derivative_authority_slice: &[&[&[u8]]] = &[derivative_authority_seeds];
 
-        if token_info.derivative_mint == Pubkey::default() {
-            token_info.is_initialized = true;
-            token_info.original_mint = token_mint.key();
-
-            let (token_metadata_address, _token_metadata_bump) = Pubkey::find_program_address(
-                &[
-                    METADATA_STATIC_SEED,
-                    metaplex_id.as_ref(),
-                    token_mint.key().as_ref(),
-                ],
-                &metaplex_id,
-            );
+        token_info.version = TOKEN_INFO_VERSION;
+        token_info.is_initialized = true;
+        token_info.original_mint = token_mint.key();
+        fee_vault.original_mint = token_mint.key();
+
+        let token_label = if token_label_acc.owner == &crate::ID {
+            Some(TokenLabel::try_deserialize(
+                &mut &token_label_acc.data.borrow()[..],
+            )?)
+        } else {
+            None
+        };
+        let source_metadata = resolve_source_metadata(
+            &token_mint.to_account_info(),
+            token_metadata_acc,
+            token_label.as_ref(),
+        )?;
+
+        let (derivative_name, derivative_symbol) =
+            derivative_name_and_symbol(&source_metadata.name, &source_metadata.symbol);
+        let derivative_uri = source_metadata.uri;
+
+        let derivative_mint_bump = ctx.bumps.derivative_mint;
+        let derivative_mint_acc_seeds: &[&[u8]] = &[
+            DERIVATIVE_MINT_STATIC_SEED,
+            mint_key.as_ref(),
+            &[derivative_mint_bump],
+        ];
+        let full_signer_seeds: &[&[&[u8]]] =
+            &[derivative_authority_seeds, derivative_mint_acc_seeds];
 
-            require_eq!(
-                token_metadata_acc.key(),
-                token_metadata_address,
-                TwosideErrorCodes::InvalidTokenMetadataAddress
+        let metadata_mode = global_info.derivative_metadata_mode;
+        if metadata_mode == DerivativeMetadataMode::Token2022 {
+            require_keys_eq!(
+                derivative_token_program.key(),
+                spl_token_2022::ID,
+                TwosideErrorCodes::InvalidDerivativeTokenProgram
             );
+        }
+
+        // Token-2022 metadata lives in the mint itself, the mint only has
+        // to be funded for it upfront, the token program reallocs on init
+        let (mint_space, metadata_space) = match metadata_mode {
+            DerivativeMetadataMode::Metaplex => (spl_token_2022::state::Mint::LEN, 0),
+            DerivativeMetadataMode::Token2022 => {
+                let token_metadata = TokenMetadata {
+                    update_authority: OptionalNonZeroPubkey::try_from(Some(
+                        derivative_authority.key(),
+                    ))?,
+                    mint: derivative_mint.key(),
+                    name: derivative_name.clone(),
+                    symbol: derivative_symbol.clone(),
+                    uri: derivative_uri.clone(),
+                    additional_metadata: vec![],
+                };
+                (
+                    ExtensionType::try_calculate_account_len::<spl_token_2022::state::Mint>(&[
+                        ExtensionType::MetadataPointer,
+                    ])?,
+                    token_metadata.tlv_size_of()?,
+                )
+            }
+        };
+
+        create_pda_account(
+            &signer.to_account_info(),
+            &derivative_mint.to_account_info(),
+            &system_program.to_account_info(),
+            &derivative_token_program.key(),
+            mint_space,
+            ctx.accounts
+                .rent
+                .minimum_balance(mint_space + metadata_space),
+            &[derivative_mint_acc_seeds],
+        )?;
+
+        if metadata_mode == DerivativeMetadataMode::Token2022 {
+            let cpi_accounts = MetadataPointerInitialize {
+                token_program_id: derivative_token_program.to_account_info(),
+                mint: derivative_mint.to_account_info(),
+            };
+            let cpi_program = derivative_token_program.to_account_info();
+            let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+            metadata_pointer_initialize(cpi_ctx, None, Some(derivative_mint.key()))?;
+        }
+
+        let cpi_accounts = InitializeMint2 {
+            mint: derivative_mint.to_account_info(),
+        };
+        let cpi_program = derivative_token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        initialize_mint2(
+            cpi_ctx,
+            token_mint.decimals,
+            &derivative_authority.key(),
+            Some(&derivative_authority.key()),
+        )?;
 
-            let (derivative_metadata_address, _derivative_metadata_bump) =
-                Pubkey::find_program_address(
-                    &[
-                        METADATA_STATIC_SEED,
-                        metaplex_id.as_ref(),
-                        derivative_mint.key().as_ref(),
-                    ],
-                    &metaplex_id,
+        match metadata_mode {
+            DerivativeMetadataMode::Metaplex => {
+                let mpl_token_metadata_program = mpl_token_metadata_program
+                    .as_ref()
+                    .ok_or(TwosideErrorCodes::MissingMetaplexAccounts)?;
+                let derivative_metadata_acc = derivative_metadata_acc
+                    .as_ref()
+                    .ok_or(TwosideErrorCodes::MissingMetaplexAccounts)?;
+
+                require_keys_eq!(
+                    mpl_token_metadata_program.key(),
+                    metaplex_id,
+                    TwosideErrorCodes::InvalidMetaplexProgram
                 );
 
-            require_eq!(
-                derivative_metadata_acc.key(),
-                derivative_metadata_address,
-                TwosideErrorCodes::InvalidDerivativeMetadataAddress
-            );
+                let (derivative_metadata_address, _derivative_metadata_bump) =
+                    Pubkey::find_program_address(
+                        &[
+                            METADATA_STATIC_SEED,
+                            metaplex_id.as_ref(),
+                            derivative_mint.key().as_ref(),
+                        ],
+                        &metaplex_id,
+                    );
 
-            let token_metadata: Metadata =
-                Metadata::safe_deserialize(&token_metadata_acc.data.borrow())
-                    .map_err(|_| TwosideErrorCodes::UninitializedMetadata)?;
+                require_eq!(
+                    derivative_metadata_acc.key(),
+                    derivative_metadata_address,
+                    TwosideErrorCodes::InvalidDerivativeMetadataAddress
+                );
 
-            require_keys_eq!(
-                token_metadata.mint,
-                token_mint.key(),
-                TwosideErrorCodes::MetadataMintMismatch
-            );
+                let rent_info = ctx.accounts.rent.to_account_info();
+                let cpi_accounts = CreateMetadataAccountV3CpiAccounts {
+                    metadata: &derivative_metadata_acc.to_account_info(),
+                    mint: &derivative_mint.to_account_info(),
+                    mint_authority: &derivative_authority.to_account_info(),
+                    payer: &signer.to_account_info(),
+                    update_authority: (&derivative_authority.to_account_info(), true),
+                    system_program: &system_program.to_account_info(),
+                    rent: Some(&rent_info),
+                };
 
-            let mut derivative_name = format!("Liquid {}", token_metadata.name.trim_end());
-            let mut derivative_symbol = format!("li{}", token_metadata.symbol.trim_end());
+                let cpi_args = CreateMetadataAccountV3InstructionArgs {
+                    data: DataV2 {
+                        name: derivative_name,
+                        symbol: derivative_symbol,
+                        uri: derivative_uri,
+                        seller_fee_basis_points: 0,
+                        creators: None,
+                        collection: None,
+                        uses: None,
+                    },
+                    is_mutable: false,
+                    collection_details: None,
+                };
 
-            if derivative_name.as_bytes().len() > 32 {
-                derivative_name =
-                    String::from_utf8_lossy(&derivative_name.as_bytes()[..32]).to_string();
+                CreateMetadataAccountV3Cpi::new(
+                    &mpl_token_metadata_program.to_account_info(),
+                    cpi_accounts,
+                    cpi_args,
+                )
+                .invoke_signed(full_signer_seeds)?;
             }
+            DerivativeMetadataMode::Token2022 => {
+                let cpi_accounts = TokenMetadataInitialize {
+                    program_id: derivative_token_program.to_account_info(),
+                    metadata: derivative_mint.to_account_info(),
+                    update_authority: derivative_authority.to_account_info(),
+                    mint_authority: derivative_authority.to_account_info(),
+                    mint: derivative_mint.to_account_info(),
+                };
+                let cpi_program = derivative_token_program.to_account_info();
+                let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts)
+                    .with_signer(derivative_authority_slice);
+                token_metadata_initialize(
+                    cpi_ctx,
+                    derivative_name,
+                    derivative_symbol,
+                    derivative_uri,
+                )?;
 
-            if derivative_symbol.as_bytes().len() > 10 {
-                derivative_symbol =
-                    String::from_utf8_lossy(&derivative_symbol.as_bytes()[..10]).to_string();
+                // match the Metaplex path, where metadata is immutable
+                let cpi_accounts = TokenMetadataUpdateAuthority {
+                    program_id: derivative_token_program.to_account_info(),
+                    metadata: derivative_mint.to_account_info(),
+                    current_authority: derivative_authority.to_account_info(),
+                    new_authority: derivative_authority.to_account_info(),
+                };
+                let cpi_program = derivative_token_program.to_account_info();
+                let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts)
+                    .with_signer(derivative_authority_slice);
+                token_metadata_update_authority(cpi_ctx, OptionalNonZeroPubkey::default())?;
             }
+        }
 
-            let mint_key = token_mint.key();
-            let derivative_mint_bump = ctx.bumps.derivative_mint;
-            let derivative_mint_acc_seeds: &[&[u8]] = &[
-                DERIVATIVE_MINT_STATIC_SEED,
-                mint_key.as_ref(),
-                &[derivative_mint_bump],
-            ];
-            let full_signer_seeds: &[&[&[u8]]] =
-                &[derivative_authority_seeds, derivative_mint_acc_seeds];
-
-            let rent_info = ctx.accounts.rent.to_account_info();
-            let cpi_accounts = CreateMetadataAccountV3CpiAccounts {
-                metadata: &derivative_metadata_acc.to_account_info(),
-                mint: &derivative_mint.to_account_info(),
-                mint_authority: &derivative_authority.to_account_info(),
-                payer: &signer.to_account_info(),
-                update_authority: (&derivative_authority.to_account_info(), true),
-                system_program: &system_program.to_account_info(),
-                rent: Some(&rent_info),
-            };
+        token_info.derivative_mint = derivative_mint.key();
 
-            let cpi_args = CreateMetadataAccountV3InstructionArgs {
-                data: DataV2 {
-                    name: derivative_name,
-                    symbol: derivative_symbol,
-                    uri: token_metadata.uri,
-                    seller_fee_basis_points: 0,
-                    creators: None,
-                    collection: None,
-                    uses: None,
-                },
-                is_mutable: false,
-                collection_details: None,
-            };
+        events.emit(&DerivativeTokenMinted {
+            token: token_mint.key(),
+            derivative: derivative_mint.key(),
+            timestamp: current_timestamp,
+        })?;
 
-            CreateMetadataAccountV3Cpi::new(
-                &mpl_token_metadata_program.to_account_info(),
-                cpi_accounts,
-                cpi_args,
-            )
-            .invoke_signed(full_signer_seeds)?;
+        Ok(())
+    }
 
-            token_info.derivative_mint = derivative_mint.key();
+    pub fn lock(ctx: Context<Lock>, amount: u64) -> Result<()> {
+        let events = event_emitter!(ctx);
+        let system_program = &ctx.accounts.system_program;
+        let token_program = &ctx.accounts.token_program;
+        let derivative_token_program = &ctx.accounts.derivative_token_program;
 
-            emit!(DerivativeTokenMinted {
-                token: token_mint.key(),
-                derivative: derivative_mint.key(),
-                timestamp: current_timestamp
-            });
-        }
+        let token_mint = &ctx.accounts.token_mint;
+        let derivative_mint = &mut ctx.accounts.derivative_mint;
+        let derivative_authority = &ctx.accounts.derivative_authority;
+        let token_info = &mut ctx.accounts.token_info;
+        let token_list_entry_acc = &ctx.accounts.token_list_entry;
+        let fee_tiers_acc = &ctx.accounts.fee_tiers;
+        let signer_fee_exemption_acc = &ctx.accounts.signer_fee_exemption;
+        let caller_fee_exemption_acc = &ctx.accounts.caller_fee_exemption;
+        let token_price_feed_acc = &ctx.accounts.token_price_feed;
+        let sol_price_feed_acc = &ctx.accounts.sol_price_feed;
+        let sysvar_instructions = &ctx.accounts.sysvar_instructions;
+        let vault_authority = &ctx.accounts.vault_authority;
+        let vault_ata = &mut ctx.accounts.vault_ata;
+        let fee_vault = &mut ctx.accounts.fee_vault;
+        let fee_vault_ata = &mut ctx.accounts.fee_vault_ata;
+
+        let global_info = &ctx.accounts.global_info;
+
+        let signer = &ctx.accounts.signer;
+        let signer_token_ata = &ctx.accounts.signer_token_ata;
+        let signer_derivative_ata = &ctx.accounts.signer_derivative_ata;
+
+        let referrer_acc = &ctx.accounts.referrer;
+        let referrer_token_account_acc = &ctx.accounts.referrer_token_account;
+        let referrer_stats_acc = &mut ctx.accounts.referrer_stats;
+        let referrer_entry_acc = &ctx.accounts.referrer_entry;
+        let user_volume_acc = &mut ctx.accounts.user_volume;
+
+        require!(amount != 0, TwosideErrorCodes::ZeroAmountValue);
+        require!(
+            (global_info.paused | token_info.paused) & PAUSE_LOCK == 0,
+            TwosideErrorCodes::Paused
+        );
+        check_token_list(global_info, token_list_entry_acc)?;
+        validate_mint_extensions(&token_mint.to_account_info())?;
+
+        let clock = Clock::get()?;
+        let current_timestamp = clock.unix_timestamp;
+
+        let mint_key = token_mint.key();
+        let derivative_authority_seeds: &[&[u8]] = &[
+            DERIVATIVE_AUTHORITY_SEED,
+            mint_key.as_ref(),
+            &[ctx.bumps.derivative_authority],
+        ];
+        let derivative_authority_slice: &[&[&[u8]]] = &[derivative_authority_seeds];
 
         require!(
             derivative_mint.key() == token_info.derivative_mint,
@@ -216,107 +783,288 @@ pub mod twoside {
         };
         let cpi_program = token_program.to_account_info();
         let cpi_context = CpiContext::new(cpi_program, cpi_accounts);
+        // vault_ata was deserialized when the instruction started and nothing
+        // has written to it since, so no reload is needed before the transfer
+        let vault_balance_before = vault_ata.amount;
         transfer_checked(cpi_context, amount, token_mint.decimals)?;
 
-        let fee = calculate_fee(
-            amount,
-            global_info.fee_percentage as u64,
-            global_info.fee_percentage_divider as u64,
-            global_info.min_fee_for_distribution as u64,
-            global_info.min_fee as u64,
+        // only back what arrived, and only if it is what the mint's transfer
+        // fee accounts for, so nothing else can skim the deposit
+        vault_ata.reload()?;
+        let received = vault_ata
+            .amount
+            .checked_sub(vault_balance_before)
+            .ok_or(TwosideErrorCodes::Overflow)?;
+        require!(
+            received
+                == expected_transfer_amount(&token_mint.to_account_info(), amount, clock.epoch)?,
+            TwosideErrorCodes::TransferAmountMismatch
+        );
+        require!(received != 0, TwosideErrorCodes::ZeroAmountValue);
+
+        let fee_exempt = is_fee_exempt(
+            signer_fee_exemption_acc,
+            caller_fee_exemption_acc.as_deref(),
+            sysvar_instructions,
         )?;
-        let deducted_amount = amount - fee;
+        let volume =
+            record_user_volume(user_volume_acc.as_deref_mut(), signer, token_mint, received);
+        let (fee, fee_lamports) = if fee_exempt {
+            (0, 0)
+        } else {
+            let fee_config = resolve_fee_config(global_info, token_info, FeeLeg::Lock);
+            let fee_tiers = read_fee_tiers(fee_tiers_acc)?;
+            let sol_fee = resolve_sol_fee(
+                token_info.sol_fee,
+                token_price_feed_acc.as_deref(),
+                sol_price_feed_acc.as_deref(),
+                current_timestamp,
+            )?;
+            calculate_fee_charge(
+                received,
+                &fee_config,
+                &fee_tiers,
+                volume,
+                sol_fee,
+                token_mint.decimals,
+            )?
+        };
+        let deducted_amount = received - fee;
 
-        distribute_fee(
+        let fee_context = FeeContext {
             token_mint,
-            fee,
-            current_timestamp,
+            leg: FeeLeg::Lock,
+            timestamp: current_timestamp,
             global_info,
-            developer_ata,
-            founder_ata,
+            signer,
+            system_program,
+            token_program,
             vault_authority,
-            ctx.bumps.vault_authority,
+            vault_authority_bump: ctx.bumps.vault_authority,
             vault_ata,
-            token_program,
-        )?;
+            events: &events,
+        };
+        let referral = match (
+            referrer_acc,
+            referrer_token_account_acc,
+            referrer_stats_acc.as_mut(),
+            referrer_entry_acc,
+        ) {
+            (
+                Some(referrer),
+                Some(referrer_token_account),
+                Some(referrer_stats),
+                Some(referrer_entry),
+            ) => {
+                require_keys_neq!(
+                    referrer.key(),
+                    signer.key(),
+                    TwosideErrorCodes::SelfReferral
+                );
+                // only admin-listed referrers get a share, otherwise any
+                // second wallet of the signer would turn it into a rebate
+                require!(
+                    read_referrer_entry(referrer_entry)?,
+                    TwosideErrorCodes::ReferrerNotAllowed
+                );
+                pay_referral_fee(
+                    &fee_context,
+                    fee,
+                    fee_lamports,
+                    received,
+                    referrer,
+                    referrer_token_account,
+                    referrer_stats,
+                )?
+            }
+            (None, None, None, None) => (0, 0),
+            _ => return err!(TwosideErrorCodes::InvalidReferrerAccounts),
+        };
+        let (referral_fee, referral_lamports) = referral;
+
+        if token_info.sol_fee.is_some() {
+            collect_sol_fee(&fee_context, fee_lamports - referral_lamports, fee_vault)?;
+        } else {
+            collect_fee(&fee_context, fee - referral_fee, fee_vault, fee_vault_ata)?;
+        }
 
         let cpi_accounts = MintTo {
             mint: derivative_mint.to_account_info(),
             to: signer_derivative_ata.to_account_info(),
             authority: derivative_authority.to_account_info(),
         };
-        let cpi_program = token_program.to_account_info();
+        let cpi_program = derivative_token_program.to_account_info();
         let cpi_ctx =
             CpiContext::new(cpi_program, cpi_accounts).with_signer(derivative_authority_slice);
 
         mint_to(cpi_ctx, deducted_amount)?;
 
-        emit!(AssetsLocked {
+        token_info.record_lock(deducted_amount, fee)?;
+        assert_solvency(vault_ata, derivative_mint)?;
+
+        events.emit(&AssetsLocked {
             account: signer.key(),
             token: token_mint.key(),
-            amount: amount,
-            timestamp: current_timestamp
-        });
-
+            amount,
+            timestamp: current_timestamp,
+        })?;
+        events.emit(&AssetsLockedV2 {
+            account: signer.key(),
+            token: token_mint.key(),
+            derivative_mint: derivative_mint.key(),
+            source_ata: signer_token_ata.key(),
+            destination_ata: signer_derivative_ata.key(),
+            amount: received,
+            fee,
+            fee_lamports,
+            net_amount: deducted_amount,
+            vault_balance: vault_ata.amount,
+            derivative_supply: derivative_mint.supply,
+            slot: clock.slot,
+            timestamp: current_timestamp,
+        })?;
+
         Ok(())
     }
 
     pub fn unlock(ctx: Context<Unlock>, amount: u64) -> Result<()> {
+        let events = event_emitter!(ctx);
+        let system_program = &ctx.accounts.system_program;
         let token_program = &ctx.accounts.token_program;
+        let derivative_token_program = &ctx.accounts.derivative_token_program;
 
         let token_mint = &ctx.accounts.token_mint;
-        let derivative_mint_acc = &ctx.accounts.derivative_mint;
-        let token_info = &ctx.accounts.token_info;
+        let derivative_mint_acc = &mut ctx.accounts.derivative_mint;
+        let token_info = &mut ctx.accounts.token_info;
+        let fee_tiers_acc = &ctx.accounts.fee_tiers;
+        let signer_fee_exemption_acc = &ctx.accounts.signer_fee_exemption;
+        let caller_fee_exemption_acc = &ctx.accounts.caller_fee_exemption;
+        let token_price_feed_acc = &ctx.accounts.token_price_feed;
+        let sol_price_feed_acc = &ctx.accounts.sol_price_feed;
+        let sysvar_instructions = &ctx.accounts.sysvar_instructions;
         let vault_authority = &ctx.accounts.vault_authority;
-        let vault_ata = &ctx.accounts.vault_ata;
+        let vault_ata = &mut ctx.accounts.vault_ata;
+        let fee_vault = &mut ctx.accounts.fee_vault;
+        let fee_vault_ata = &mut ctx.accounts.fee_vault_ata;
 
         let global_info = &ctx.accounts.global_info;
-        let founder_ata = &ctx.accounts.founder_ata;
-        let developer_ata = &ctx.accounts.developer_ata;
 
         let signer = &ctx.accounts.signer;
         let signer_token_ata = &ctx.accounts.signer_token_ata;
         let signer_derivative_ata = &ctx.accounts.signer_derivative_ata;
 
+        let referrer_acc = &ctx.accounts.referrer;
+        let referrer_token_account_acc = &ctx.accounts.referrer_token_account;
+        let referrer_stats_acc = &mut ctx.accounts.referrer_stats;
+        let referrer_entry_acc = &ctx.accounts.referrer_entry;
+        let user_volume_acc = &mut ctx.accounts.user_volume;
+
         require!(amount != 0, TwosideErrorCodes::ZeroAmountValue);
         require!(
             token_info.derivative_mint != Pubkey::default(),
             TwosideErrorCodes::NoDerivativeDeployed
         );
-
-        let fee = calculate_fee(
-            amount,
-            global_info.fee_percentage as u64,
-            global_info.fee_percentage_divider as u64,
-            global_info.min_fee_for_distribution as u64,
-            global_info.min_fee as u64,
-        )?;
-        let deducted_amount = amount - fee;
+        require!(
+            (global_info.paused | token_info.paused) & PAUSE_UNLOCK == 0,
+            TwosideErrorCodes::Paused
+        );
 
         let clock = Clock::get()?;
         let current_timestamp = clock.unix_timestamp;
 
-        distribute_fee(
+        let fee_exempt = is_fee_exempt(
+            signer_fee_exemption_acc,
+            caller_fee_exemption_acc.as_deref(),
+            sysvar_instructions,
+        )?;
+        let volume = record_user_volume(user_volume_acc.as_deref_mut(), signer, token_mint, amount);
+        let (fee, fee_lamports) = if fee_exempt {
+            (0, 0)
+        } else {
+            let fee_config = resolve_fee_config(global_info, token_info, FeeLeg::Unlock);
+            let fee_tiers = read_fee_tiers(fee_tiers_acc)?;
+            let sol_fee = resolve_sol_fee(
+                token_info.sol_fee,
+                token_price_feed_acc.as_deref(),
+                sol_price_feed_acc.as_deref(),
+                current_timestamp,
+            )?;
+            calculate_fee_charge(
+                amount,
+                &fee_config,
+                &fee_tiers,
+                volume,
+                sol_fee,
+                token_mint.decimals,
+            )?
+        };
+        let deducted_amount = amount - fee;
+
+        let fee_context = FeeContext {
             token_mint,
-            fee,
-            current_timestamp,
+            leg: FeeLeg::Unlock,
+            timestamp: current_timestamp,
             global_info,
-            developer_ata,
-            founder_ata,
+            signer,
+            system_program,
+            token_program,
             vault_authority,
-            ctx.bumps.vault_authority,
+            vault_authority_bump: ctx.bumps.vault_authority,
             vault_ata,
-            token_program,
-        )?;
+            events: &events,
+        };
+        let referral = match (
+            referrer_acc,
+            referrer_token_account_acc,
+            referrer_stats_acc.as_mut(),
+            referrer_entry_acc,
+        ) {
+            (
+                Some(referrer),
+                Some(referrer_token_account),
+                Some(referrer_stats),
+                Some(referrer_entry),
+            ) => {
+                require_keys_neq!(
+                    referrer.key(),
+                    signer.key(),
+                    TwosideErrorCodes::SelfReferral
+                );
+                // only admin-listed referrers get a share, otherwise any
+                // second wallet of the signer would turn it into a rebate
+                require!(
+                    read_referrer_entry(referrer_entry)?,
+                    TwosideErrorCodes::ReferrerNotAllowed
+                );
+                pay_referral_fee(
+                    &fee_context,
+                    fee,
+                    fee_lamports,
+                    amount,
+                    referrer,
+                    referrer_token_account,
+                    referrer_stats,
+                )?
+            }
+            (None, None, None, None) => (0, 0),
+            _ => return err!(TwosideErrorCodes::InvalidReferrerAccounts),
+        };
+        let (referral_fee, referral_lamports) = referral;
+
+        if token_info.sol_fee.is_some() {
+            collect_sol_fee(&fee_context, fee_lamports - referral_lamports, fee_vault)?;
+        } else {
+            collect_fee(&fee_context, fee - referral_fee, fee_vault, fee_vault_ata)?;
+        }
 
         let cpi_accounts = Burn {
             from: signer_derivative_ata.to_account_info(),
             mint: derivative_mint_acc.to_account_info(),
             authority: signer.to_account_info(),
         };
-        let cpi_program = token_program.to_account_info();
+        let cpi_program = derivative_token_program.to_account_info();
         let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-        token::burn(cpi_ctx, amount)?;
+        token_interface::burn(cpi_ctx, amount)?;
 
         let mint_key = token_mint.key();
         let vault_authority_seeds: &[&[u8]] = &[
@@ -337,12 +1085,109 @@ pub mod twoside {
             CpiContext::new(cpi_program, cpi_accounts).with_signer(vault_authority_slice);
         transfer_checked(cpi_context, deducted_amount, token_mint.decimals)?;
 
-        emit!(AssetsUnlocked {
+        token_info.record_unlock(amount, fee)?;
+        assert_solvency(vault_ata, derivative_mint_acc)?;
+
+        events.emit(&AssetsUnlocked {
             account: signer.key(),
             token: token_mint.key(),
-            amount: amount,
-            timestamp: current_timestamp
-        });
+            amount,
+            timestamp: current_timestamp,
+        })?;
+        events.emit(&AssetsUnlockedV2 {
+            account: signer.key(),
+            token: token_mint.key(),
+            derivative_mint: derivative_mint_acc.key(),
+            source_ata: signer_derivative_ata.key(),
+            destination_ata: signer_token_ata.key(),
+            amount,
+            fee,
+            fee_lamports,
+            net_amount: deducted_amount,
+            vault_balance: vault_ata.amount,
+            derivative_supply: derivative_mint_acc.supply,
+            slot: clock.slot,
+            timestamp: current_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    pub fn verify_solvency(ctx: Context<VerifySolvency>) -> Result<()> {
+        let events = event_emitter!(ctx);
+        let token_mint = &ctx.accounts.token_mint;
+        let derivative_mint = &ctx.accounts.derivative_mint;
+        let vault_ata = &ctx.accounts.vault_ata;
+
+        // reports either way, an insolvent token is what monitoring is after
+        let clock = Clock::get()?;
+        events.emit(&SolvencyReport {
+            token: token_mint.key(),
+            vault_balance: vault_ata.amount,
+            derivative_supply: derivative_mint.supply,
+            surplus: vault_ata.amount as i128 - derivative_mint.supply as i128,
+            timestamp: clock.unix_timestamp,
+        })?;
+
+        Ok(())
+    }
+
+    pub fn claim_fees(ctx: Context<ClaimFees>) -> Result<()> {
+        let events = event_emitter!(ctx);
+        let token_program = &ctx.accounts.token_program;
+
+        let token_mint = &ctx.accounts.token_mint;
+        let fee_vault = &mut ctx.accounts.fee_vault;
+        let fee_vault_ata = &ctx.accounts.fee_vault_ata;
+
+        let signer = &ctx.accounts.signer;
+        let recipient = &ctx.accounts.recipient;
+        let recipient_ata = &ctx.accounts.recipient_ata;
+
+        let balance = fee_vault.take(recipient.key());
+        require!(
+            balance.amount != 0 || balance.lamports != 0,
+            TwosideErrorCodes::NoFeesToClaim
+        );
+
+        let mint_key = token_mint.key();
+        let fee_vault_seeds: &[&[u8]] = &[
+            FEE_VAULT_STATIC_SEED,
+            mint_key.as_ref(),
+            &[ctx.bumps.fee_vault],
+        ];
+        let fee_vault_slice: &[&[&[u8]]] = &[fee_vault_seeds];
+
+        if balance.amount > 0 {
+            let cpi_accounts = TransferChecked {
+                mint: token_mint.to_account_info(),
+                from: fee_vault_ata.to_account_info(),
+                to: recipient_ata.to_account_info(),
+                authority: fee_vault.to_account_info(),
+            };
+            let cpi_program = token_program.to_account_info();
+            let cpi_context =
+                CpiContext::new(cpi_program, cpi_accounts).with_signer(fee_vault_slice);
+            transfer_checked(cpi_context, balance.amount, token_mint.decimals)?;
+        }
+
+        // the fee vault is owned by this program, so SOL fees move directly
+        if balance.lamports > 0 {
+            fee_vault.sub_lamports(balance.lamports)?;
+            recipient.add_lamports(balance.lamports)?;
+        }
+
+        let clock = Clock::get()?;
+        let current_timestamp = clock.unix_timestamp;
+
+        events.emit(&FeesClaimed {
+            claimer: signer.key(),
+            recipient: recipient.key(),
+            token: mint_key,
+            amount: balance.amount,
+            lamports: balance.lamports,
+            timestamp: current_timestamp,
+        })?;
 
         Ok(())
     }
@@ -350,31 +1195,35 @@ pub mod twoside {
 
 pub fn calculate_fee(
     amount: u64,
-    fee_percentage: u64,
-    fee_percentage_divider: u64,
-    min_fee_for_distribution: u64,
-    min_fee: u64,
+    fee_config: &FeeConfig,
+    fee_tiers: &[FeeTier],
+    tier_metric: u64,
 ) -> Result<u64> {
+    // tiers are sorted by threshold, the last one reached wins, and only ever
+    // discount the leg's rate
+    let fee_bps = fee_tiers
+        .iter()
+        .rev()
+        .find(|tier| tier_metric >= tier.threshold)
+        .map_or(fee_config.fee_bps, |tier| {
+            tier.fee_bps.min(fee_config.fee_bps)
+        });
+
     let amount128 = amount as u128;
-    let fee_percentage128 = fee_percentage as u128;
-    let fee_percentage_divider128 = fee_percentage_divider as u128;
-    let min_fee_for_distribution128 = min_fee_for_distribution as u128;
+    let fee_bps128 = fee_bps as u128;
 
     let numer = amount128
-        .checked_mul(fee_percentage128)
+        .checked_mul(fee_bps128)
         .ok_or(TwosideErrorCodes::Overflow)?;
-    let half = fee_percentage_divider128
-        .checked_div(2)
+    let summed = numer
+        .checked_add(BASIS_POINTS_DIVIDER as u128 / 2)
         .ok_or(TwosideErrorCodes::Overflow)?;
-    let summed = numer.checked_add(half).ok_or(TwosideErrorCodes::Overflow)?;
-    let rounded = summed / fee_percentage_divider128;
+    let rounded = summed / BASIS_POINTS_DIVIDER as u128;
 
-    // if rounding produced zero use min_fee, otherwise try to convert to u64 (fail if too big)
-    let fee_u64 = if rounded < min_fee_for_distribution128 {
-        min_fee
-    } else {
-        u64::try_from(rounded).map_err(|_| TwosideErrorCodes::Overflow)?
-    };
+    // amount * fee_bps / 10_000 < amount, so this always fits in u64
+    let fee_u64 = u64::try_from(rounded)
+        .map_err(|_| TwosideErrorCodes::Overflow)?
+        .clamp(fee_config.min_fee, fee_config.max_fee);
 
     // final sanity: ensure fee leaves something to lock
     require!(
@@ -385,344 +1234,2391 @@ pub fn calculate_fee(
     Ok(fee_u64)
 }
 
-pub fn distribute_fee<'info>(
-    token_mint: &Account<'info, Mint>,
-    fee: u64,
-    timestamp: i64,
-    global_info: &Account<'info, GlobalInfo>,
-    developer_ata: &Account<'info, TokenAccount>,
-    founder_ata: &Account<'info, TokenAccount>,
-    vault_authority: &UncheckedAccount<'info>,
-    vault_authority_bump: u8,
-    vault_ata: &Account<'info, TokenAccount>,
-    token_program: &Program<'info, Token>,
-) -> Result<()> {
-    let developer_share = fee
-        .checked_mul(global_info.developer_fee_share as u64)
-        .ok_or(TwosideErrorCodes::Overflow)?
-        .checked_div(100)
+// Fee in the locked token and in lamports, at most one of them is non-zero
+pub fn calculate_fee_charge(
+    amount: u64,
+    fee_config: &FeeConfig,
+    fee_tiers: &FeeTierSchedule,
+    volume: u64,
+    sol_fee: Option<SolFeeCharge>,
+    decimals: u8,
+) -> Result<(u64, u64)> {
+    let tier_metric = match fee_tiers.basis {
+        FeeTierBasis::Amount => amount,
+        FeeTierBasis::Volume => volume,
+    };
+
+    match sol_fee {
+        None => Ok((
+            calculate_fee(amount, fee_config, &fee_tiers.tiers, tier_metric)?,
+            0,
+        )),
+        Some(SolFeeCharge::Flat(lamports)) => Ok((0, lamports)),
+        Some(SolFeeCharge::Priced {
+            token_price,
+            sol_price,
+        }) => {
+            // the token fee is only used to value the SOL charge
+            let token_fee = calculate_fee(amount, fee_config, &fee_tiers.tiers, tier_metric)?;
+            let lamports = value_in_lamports(token_fee, decimals, &token_price, &sol_price)?;
+            // a bad price must not drain the signer
+            require!(
+                lamports <= MAX_SOL_FEE_LAMPORTS,
+                TwosideErrorCodes::SolFeeTooHigh
+            );
+            Ok((0, lamports))
+        }
+    }
+}
+
+// `token_fee` base units of a token with `decimals`, converted through the USD
+// prices of the token and of SOL
+pub fn value_in_lamports(
+    token_fee: u64,
+    decimals: u8,
+    token_price: &PriceFeedMessage,
+    sol_price: &PriceFeedMessage,
+) -> Result<u64> {
+    // lamports = fee * token_price * 10^(token_exp - sol_exp + 9 - decimals) / sol_price
+    let scale = token_price.exponent - sol_price.exponent + LAMPORTS_DECIMALS - decimals as i32;
+    let factor = 10u128
+        .checked_pow(scale.unsigned_abs())
         .ok_or(TwosideErrorCodes::Overflow)?;
-    let founder_share = fee
-        .checked_mul(global_info.founder_fee_share as u64)
-        .ok_or(TwosideErrorCodes::Overflow)?
-        .checked_div(100)
+    let mut numerator = (token_fee as u128)
+        .checked_mul(token_price.price as u128)
         .ok_or(TwosideErrorCodes::Overflow)?;
+    let mut denominator = sol_price.price as u128;
+    if scale >= 0 {
+        numerator = numerator
+            .checked_mul(factor)
+            .ok_or(TwosideErrorCodes::Overflow)?;
+    } else {
+        denominator = denominator
+            .checked_mul(factor)
+            .ok_or(TwosideErrorCodes::Overflow)?;
+    }
+    u64::try_from(numerator / denominator).map_err(|_| TwosideErrorCodes::Overflow.into())
+}
 
-    let mint_key = token_mint.key();
-    let seeds: &[&[u8]] = &[
-        VAULT_AUTHORITY_STATIC_SEED,
-        mint_key.as_ref(),
-        &[vault_authority_bump],
-    ];
-    let signer_slice: &[&[&[u8]]] = &[seeds];
-
-    let cpi_accounts = TransferChecked {
-        mint: token_mint.to_account_info(),
-        from: vault_ata.to_account_info(),
-        to: developer_ata.to_account_info(),
-        authority: vault_authority.to_account_info(),
+// Reads the oracle prices a priced SOL fee needs, only called when a fee is due
+pub fn resolve_sol_fee(
+    sol_fee: Option<SolFee>,
+    token_price_feed: Option<&AccountInfo>,
+    sol_price_feed: Option<&AccountInfo>,
+    now: i64,
+) -> Result<Option<SolFeeCharge>> {
+    let charge = match sol_fee {
+        None => None,
+        Some(SolFee::Flat { lamports }) => Some(SolFeeCharge::Flat(lamports)),
+        Some(SolFee::Priced {
+            token_price_feed: token_price_feed_key,
+            sol_price_feed: sol_price_feed_key,
+            max_price_age,
+        }) => {
+            let token_price_feed = token_price_feed.ok_or(TwosideErrorCodes::InvalidPriceFeed)?;
+            let sol_price_feed = sol_price_feed.ok_or(TwosideErrorCodes::InvalidPriceFeed)?;
+            Some(SolFeeCharge::Priced {
+                token_price: read_oracle_price(
+                    token_price_feed,
+                    token_price_feed_key,
+                    max_price_age,
+                    now,
+                )?,
+                sol_price: read_oracle_price(
+                    sol_price_feed,
+                    sol_price_feed_key,
+                    max_price_age,
+                    now,
+                )?,
+            })
+        }
     };
-    let cpi_program = token_program.to_account_info();
-    let cpi_context = CpiContext::new(cpi_program, cpi_accounts).with_signer(signer_slice);
-    transfer_checked(cpi_context, developer_share, token_mint.decimals)?;
+    Ok(charge)
+}
+
+// Latest price from a Pyth price update account, which must be the configured
+// feed, fully verified and published at most `max_age` seconds ago
+pub fn read_oracle_price(
+    price_feed: &AccountInfo,
+    expected_price_feed: Pubkey,
+    max_age: u32,
+    now: i64,
+) -> Result<PriceFeedMessage> {
+    require_keys_eq!(
+        price_feed.key(),
+        expected_price_feed,
+        TwosideErrorCodes::InvalidPriceFeed
+    );
+    require_keys_eq!(
+        *price_feed.owner,
+        PYTH_RECEIVER_PROGRAM_ID,
+        TwosideErrorCodes::InvalidPriceFeed
+    );
+    let data = price_feed.try_borrow_data()?;
+    require!(
+        data.starts_with(&PRICE_UPDATE_V2_DISCRIMINATOR),
+        TwosideErrorCodes::InvalidPriceFeed
+    );
+    let update = PriceUpdateV2::deserialize(&mut &data[PRICE_UPDATE_V2_DISCRIMINATOR.len()..])?;
+    require!(
+        update.verification_level == VerificationLevel::Full,
+        TwosideErrorCodes::InvalidPriceFeed
+    );
+
+    let price = update.price_message;
+    require!(
+        price.publish_time.saturating_add(max_age as i64) >= now,
+        TwosideErrorCodes::StalePrice
+    );
+    require!(price.price > 0, TwosideErrorCodes::InvalidOraclePrice);
+    // too wide a confidence interval and the price could be far off either way
+    require!(
+        price.conf as u128 * BASIS_POINTS_DIVIDER as u128
+            <= price.price as u128 * MAX_PRICE_CONF_BPS as u128,
+        TwosideErrorCodes::InvalidOraclePrice
+    );
+    Ok(price)
+}
+
+pub fn validate_global_config(config: &GlobalConfig) -> Result<()> {
+    require!(
+        !config.fee_recipients.is_empty() && config.fee_recipients.len() <= MAX_FEE_RECIPIENTS,
+        TwosideErrorCodes::InvalidFeeRecipients
+    );
+    require!(
+        (config.fee_remainder_index as usize) < config.fee_recipients.len(),
+        TwosideErrorCodes::InvalidFeeRecipients
+    );
+    for recipient in &config.fee_recipients {
+        require!(
+            recipient.wallet != Pubkey::default(),
+            TwosideErrorCodes::InvalidPubkey
+        );
+    }
+    let total_weight: u32 = config
+        .fee_recipients
+        .iter()
+        .map(|recipient| recipient.weight_bps as u32)
+        .sum();
+    require!(
+        total_weight as u64 == BASIS_POINTS_DIVIDER,
+        TwosideErrorCodes::InvalidFeeShares
+    );
+    require!(
+        config.referral_fee_bps as u64 <= BASIS_POINTS_DIVIDER,
+        TwosideErrorCodes::InvalidReferralFee
+    );
+    validate_fee_config(&config.lock_fee)?;
+    validate_fee_config(&config.unlock_fee)
+}
+
+pub fn validate_fee_config(fee_config: &FeeConfig) -> Result<()> {
+    require!(
+        fee_config.fee_bps as u64 <= MAX_FEE_BASIS_POINTS,
+        TwosideErrorCodes::FeeTooHigh
+    );
+    require!(
+        fee_config.min_fee <= fee_config.max_fee,
+        TwosideErrorCodes::InvalidFeeBounds
+    );
 
-    let cpi_accounts = TransferChecked {
-        mint: token_mint.to_account_info(),
-        from: vault_ata.to_account_info(),
-        to: founder_ata.to_account_info(),
-        authority: vault_authority.to_account_info(),
-    };
-    let cpi_program = token_program.to_account_info();
-    let cpi_context = CpiContext::new(cpi_program, cpi_accounts).with_signer(signer_slice);
-    transfer_checked(cpi_context, founder_share, token_mint.decimals)?;
-
-    // add ata field
-    emit!(DeveloperFeeShareDistributed {
-        developer_wallet: global_info.developer_wallet,
-        token: token_mint.key(),
-        amount: developer_share,
-        timestamp: timestamp
-    });
-    emit!(FounderFeeShareDistributed {
-        founder_wallet: global_info.founder_wallet,
-        token: token_mint.key(),
-        amount: founder_share,
-        timestamp: timestamp
-    });
     Ok(())
 }
 
-#[derive(Accounts)]
-pub struct InitializeProgram<'info> {
-    pub system_program: Program<'info, System>,
-    #[account(mut)]
-    pub signer: Signer<'info>,
-    #[account(
-        init,
-        seeds = [GLOBAL_INFO_STATIC_SEED],
-        bump,
-        payer = signer,
-        space = 8 + GlobalInfo::LEN,
-    )]
-    pub global_info: Account<'info, GlobalInfo>,
+pub fn validate_sol_fee(sol_fee: &SolFee) -> Result<()> {
+    match *sol_fee {
+        SolFee::Flat { lamports } => {
+            require!(
+                lamports <= MAX_SOL_FEE_LAMPORTS,
+                TwosideErrorCodes::SolFeeTooHigh
+            );
+        }
+        SolFee::Priced {
+            token_price_feed,
+            sol_price_feed,
+            max_price_age,
+        } => {
+            require!(
+                token_price_feed != Pubkey::default() && sol_price_feed != Pubkey::default(),
+                TwosideErrorCodes::InvalidPubkey
+            );
+            require!(
+                max_price_age != 0 && max_price_age <= MAX_PRICE_AGE_SECONDS,
+                TwosideErrorCodes::InvalidPriceAge
+            );
+        }
+    }
+
+    Ok(())
 }
 
-#[derive(Accounts)]
-pub struct Lock<'info> {
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    /// CHECK: This is the Metaplex Token Metadata program
-    pub mpl_token_metadata_program: UncheckedAccount<'info>,
+// `base_fee_bps` is the highest rate the tiers discount, calculate_fee caps
+// each leg at its own rate
+pub fn validate_fee_tiers(fee_tiers: &[FeeTier], base_fee_bps: u16) -> Result<()> {
+    require!(
+        fee_tiers.len() <= MAX_FEE_TIERS,
+        TwosideErrorCodes::InvalidFeeTiers
+    );
+    for tier in fee_tiers {
+        require!(
+            tier.fee_bps as u64 <= MAX_FEE_BASIS_POINTS,
+            TwosideErrorCodes::FeeTooHigh
+        );
+        require!(
+            tier.fee_bps <= base_fee_bps,
+            TwosideErrorCodes::FeeTierAboveBaseRate
+        );
+    }
+    // higher thresholds must never cost more
+    for pair in fee_tiers.windows(2) {
+        require!(
+            pair[0].threshold < pair[1].threshold && pair[0].fee_bps >= pair[1].fee_bps,
+            TwosideErrorCodes::InvalidFeeTiers
+        );
+    }
+    Ok(())
+}
 
-    /// CHECK: Instructions sysvar must be passed in
-    #[account(address = anchor_lang::solana_program::sysvar::ID)]
-    pub sysvar_instructions: UncheckedAccount<'info>,
-    pub rent: Sysvar<'info, Rent>,
+pub fn read_fee_tiers(fee_tiers: &AccountInfo) -> Result<FeeTierSchedule> {
+    // schedules only exist once the admin set one, fall back to the flat fee
+    if fee_tiers.owner != &crate::ID {
+        return Ok(FeeTierSchedule {
+            original_mint: Pubkey::default(),
+            basis: FeeTierBasis::Amount,
+            tiers: Vec::new(),
+        });
+    }
+    FeeTierSchedule::try_deserialize(&mut &fee_tiers.data.borrow()[..])
+}
 
-    #[account(
-        mut,
-        constraint = token_mint.is_initialized
-        @ ProgramError::UninitializedAccount
-    )]
-    pub token_mint: Box<Account<'info, Mint>>,
-    /// CHECK: Metadata for token being locked
-    #[account(
-        mut,
-        constraint = token_metadata.owner == &metaplex_id
-        @ ProgramError::IncorrectProgramId
-    )]
-    pub token_metadata: AccountInfo<'info>,
+pub fn record_user_volume(
+    user_volume: Option<&mut Account<UserVolume>>,
+    signer: &Signer,
+    token_mint: &InterfaceAccount<Mint>,
+    amount: u64,
+) -> u64 {
+    let Some(user_volume) = user_volume else {
+        return 0;
+    };
+    user_volume.owner = signer.key();
+    user_volume.original_mint = token_mint.key();
+    // only ever lowers the fee, saturate rather than block a lock or unlock
+    user_volume.volume = user_volume.volume.saturating_add(amount);
+    user_volume.volume
+}
 
-    /// CHECK: Derivative Token's Mint Authority.
-    #[account(
-        seeds = [
-        DERIVATIVE_AUTHORITY_SEED,
-        token_mint.key().as_ref()
-        ], bump
-    )]
-    pub derivative_authority: UncheckedAccount<'info>,
-    #[account(
-        init_if_needed,
-        payer = signer,
-        mint::decimals = token_mint.decimals,
-        mint::authority = derivative_authority,
-        mint::freeze_authority = derivative_authority,
-        seeds = [DERIVATIVE_MINT_STATIC_SEED, token_mint.key().as_ref()],
-        bump
-    )]
-    pub derivative_mint: Box<Account<'info, Mint>>,
-    /// CHECK: Metaplex Metadata account PDA, derived from mint and Metaplex program ID
-    #[account(
-        mut,
-        seeds = [
+// per-token override of the leg if set, global fee parameters otherwise
+pub fn resolve_fee_config(
+    global_info: &GlobalInfo,
+    token_info: &TokenInfo,
+    leg: FeeLeg,
+) -> FeeConfig {
+    match leg {
+        FeeLeg::Lock => token_info.lock_fee_override.unwrap_or(global_info.lock_fee),
+        FeeLeg::Unlock => token_info
+            .unlock_fee_override
+            .unwrap_or(global_info.unlock_fee),
+    }
+}
+
+pub struct SourceMetadata {
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+}
+
+// Picks the name the derivative is derived from, in order: admin-registered
+// label, Metaplex metadata, Token-2022 metadata extension, the mint address
+pub fn resolve_source_metadata(
+    token_mint: &AccountInfo,
+    token_metadata_acc: &AccountInfo,
+    token_label: Option<&TokenLabel>,
+) -> Result<SourceMetadata> {
+    if let Some(token_label) = token_label {
+        return Ok(SourceMetadata {
+            name: token_label.name.clone(),
+            symbol: token_label.symbol.clone(),
+            uri: token_label.uri.clone(),
+        });
+    }
+
+    // the canonical PDA is always passed so a registrant cannot hide existing
+    // metadata behind the fallbacks
+    let (token_metadata_address, _token_metadata_bump) = Pubkey::find_program_address(
+        &[
             METADATA_STATIC_SEED,
-            mpl_token_metadata_program.key().as_ref(),
-            derivative_mint.key().as_ref()
+            metaplex_id.as_ref(),
+            token_mint.key().as_ref(),
         ],
-        bump,
-        seeds::program = mpl_token_metadata_program.key()
-    )]
-    pub derivative_metadata: UncheckedAccount<'info>,
+        &metaplex_id,
+    );
+    require_eq!(
+        token_metadata_acc.key(),
+        token_metadata_address,
+        TwosideErrorCodes::InvalidTokenMetadataAddress
+    );
 
-    #[account(mut)]
-    pub signer: Signer<'info>,
-    #[account(
-        mut,
-        token::mint = token_mint,
-        token::authority = signer,
-    )]
-    pub signer_token_ata: Box<Account<'info, TokenAccount>>,
-    #[account(
-        init_if_needed,
-        payer = signer,
-        associated_token::mint = derivative_mint,
-        associated_token::authority = signer,
-    )]
-    pub signer_derivative_ata: Box<Account<'info, TokenAccount>>,
+    if !token_metadata_acc.data_is_empty() {
+        if token_metadata_acc.owner != &metaplex_id {
+            return Err(ProgramError::IncorrectProgramId.into());
+        }
+        let token_metadata: Metadata =
+            Metadata::safe_deserialize(&token_metadata_acc.data.borrow())
+                .map_err(|_| TwosideErrorCodes::UninitializedMetadata)?;
 
-    #[account(
-        init_if_needed,
-        seeds = [
-            TOKEN_INFO_STATIC_SEED,
-            token_mint.key().as_ref()
-        ],
-        bump,
-        payer = signer,
-        space = 8 + TokenInfo::LEN,
-    )]
-    pub token_info: Box<Account<'info, TokenInfo>>,
+        require_keys_eq!(
+            token_metadata.mint,
+            token_mint.key(),
+            TwosideErrorCodes::MetadataMintMismatch
+        );
 
-    #[account(
-        seeds = [
-            VAULT_AUTHORITY_STATIC_SEED,
+        return Ok(SourceMetadata {
+            name: token_metadata.name,
+            symbol: token_metadata.symbol,
+            uri: token_metadata.uri.trim_end_matches('\0').to_string(),
+        });
+    }
+
+    if *token_mint.owner == spl_token_2022::ID {
+        let mint_data = token_mint.try_borrow_data()?;
+        let mint_state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+        if let Ok(token_metadata) = mint_state.get_variable_len_extension::<TokenMetadata>() {
+            return Ok(SourceMetadata {
+                name: token_metadata.name,
+                symbol: token_metadata.symbol,
+                uri: token_metadata.uri,
+            });
+        }
+    }
+
+    let mint_address = token_mint.key().to_string();
+    Ok(SourceMetadata {
+        name: mint_address[..FALLBACK_NAME_CHARS].to_string(),
+        symbol: mint_address[..FALLBACK_SYMBOL_CHARS].to_string(),
+        uri: String::new(),
+    })
+}
+
+pub fn derivative_name_and_symbol(name: &str, symbol: &str) -> (String, String) {
+    let mut derivative_name = format!("Liquid {}", name.trim_end_matches('\0').trim_end());
+    let mut derivative_symbol = format!("li{}", symbol.trim_end_matches('\0').trim_end());
+
+    if derivative_name.len() > 32 {
+        derivative_name = String::from_utf8_lossy(&derivative_name.as_bytes()[..32]).to_string();
+    }
+
+    if derivative_symbol.len() > 10 {
+        derivative_symbol =
+            String::from_utf8_lossy(&derivative_symbol.as_bytes()[..10]).to_string();
+    }
+
+    (derivative_name, derivative_symbol)
+}
+
+// Same as Anchor's `init`, which also has to cope with lamports that were
+// sent to the PDA before it got created
+pub fn create_pda_account<'info>(
+    payer: &AccountInfo<'info>,
+    account: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    owner: &Pubkey,
+    space: usize,
+    lamports: u64,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    let current_lamports = account.lamports();
+    if current_lamports == 0 {
+        let cpi_accounts = CreateAccount {
+            from: payer.clone(),
+            to: account.clone(),
+        };
+        let cpi_ctx =
+            CpiContext::new_with_signer(system_program.clone(), cpi_accounts, signer_seeds);
+        return system_program::create_account(cpi_ctx, lamports, space as u64, owner);
+    }
+
+    let top_up = lamports.saturating_sub(current_lamports);
+    if top_up > 0 {
+        let cpi_accounts = Transfer {
+            from: payer.clone(),
+            to: account.clone(),
+        };
+        let cpi_ctx = CpiContext::new(system_program.clone(), cpi_accounts);
+        system_program::transfer(cpi_ctx, top_up)?;
+    }
+
+    let cpi_accounts = Allocate {
+        account_to_allocate: account.clone(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(system_program.clone(), cpi_accounts, signer_seeds);
+    system_program::allocate(cpi_ctx, space as u64)?;
+
+    let cpi_accounts = Assign {
+        account_to_assign: account.clone(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(system_program.clone(), cpi_accounts, signer_seeds);
+    system_program::assign(cpi_ctx, owner)
+}
+
+pub fn check_token_list(global_info: &GlobalInfo, token_list_entry: &AccountInfo) -> Result<()> {
+    // entries only exist once the admin listed the mint, treat others as unlisted
+    let status = if token_list_entry.owner == &crate::ID {
+        TokenListEntry::try_deserialize(&mut &token_list_entry.data.borrow()[..])?.status
+    } else {
+        TokenListStatus::Unlisted
+    };
+
+    let allowed = match global_info.token_list_mode {
+        TokenListMode::Open => true,
+        TokenListMode::Allowlist => status == TokenListStatus::Allowed,
+        TokenListMode::Denylist => status != TokenListStatus::Denied,
+    };
+    require!(allowed, TwosideErrorCodes::TokenNotAllowed);
+
+    Ok(())
+}
+
+pub fn is_fee_exempt(
+    signer_fee_exemption: &AccountInfo,
+    caller_fee_exemption: Option<&AccountInfo>,
+    sysvar_instructions: &AccountInfo,
+) -> Result<bool> {
+    if read_fee_exemption(signer_fee_exemption)? {
+        return Ok(true);
+    }
+
+    // a top level instruction for another program means it reached us by CPI
+    let current_ix = get_instruction_relative(0, sysvar_instructions)?;
+    if current_ix.program_id == crate::ID {
+        return Ok(false);
+    }
+    // only a direct CPI proves the top level program is our caller, deeper in
+    // the stack an intermediate program could be invoking us on its behalf
+    if get_stack_height() != TRANSACTION_LEVEL_STACK_HEIGHT + 1 {
+        return Ok(false);
+    }
+    let Some(caller_fee_exemption) = caller_fee_exemption else {
+        return Ok(false);
+    };
+    let (expected_fee_exemption, _) = Pubkey::find_program_address(
+        &[FEE_EXEMPTION_STATIC_SEED, current_ix.program_id.as_ref()],
+        &crate::ID,
+    );
+    require_keys_eq!(
+        caller_fee_exemption.key(),
+        expected_fee_exemption,
+        TwosideErrorCodes::InvalidFeeExemptionAccount
+    );
+    read_fee_exemption(caller_fee_exemption)
+}
+
+pub fn read_fee_exemption(fee_exemption: &AccountInfo) -> Result<bool> {
+    // entries only exist once the admin listed the key, treat others as not exempt
+    if fee_exemption.owner != &crate::ID {
+        return Ok(false);
+    }
+    Ok(FeeExemption::try_deserialize(&mut &fee_exemption.data.borrow()[..])?.exempt)
+}
+
+pub fn read_referrer_entry(referrer_entry: &AccountInfo) -> Result<bool> {
+    // entries only exist once the admin listed the referrer
+    if referrer_entry.owner != &crate::ID {
+        return Ok(false);
+    }
+    Ok(ReferrerEntry::try_deserialize(&mut &referrer_entry.data.borrow()[..])?.allowed)
+}
+
+// Layout version of one of our accounts, the byte after the discriminator.
+// Accounts from before versioning hold is_initialized there, so an initialized
+// one reads as version 1 and an uninitialized one as 0
+pub fn read_account_version(account: &AccountInfo, discriminator: &[u8]) -> Result<u8> {
+    if account.owner != &crate::ID {
+        return Err(ProgramError::UninitializedAccount.into());
+    }
+    let data = account.try_borrow_data()?;
+    if !data.starts_with(discriminator) {
+        return Err(ProgramError::InvalidAccountData.into());
+    }
+    match data.get(discriminator.len()) {
+        Some(version) => Ok(*version),
+        None => Err(ProgramError::InvalidAccountData.into()),
+    }
+}
+
+// Reads an account at the older layout its version picked, the length is only
+// a sanity check that the account really was written at that layout
+pub fn read_legacy_account<T: AnchorDeserialize>(
+    account: &AccountInfo,
+    discriminator: &[u8],
+    legacy_len: usize,
+) -> Result<T> {
+    if account.data_len() != discriminator.len() + legacy_len {
+        return Err(ProgramError::InvalidAccountData.into());
+    }
+
+    let data = account.try_borrow_data()?;
+    Ok(T::deserialize(&mut &data[discriminator.len()..])?)
+}
+
+pub fn resize_account<'info>(
+    account: &AccountInfo<'info>,
+    new_len: usize,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+) -> Result<()> {
+    let rent_shortfall = Rent::get()?
+        .minimum_balance(new_len)
+        .saturating_sub(account.lamports());
+    if rent_shortfall > 0 {
+        let cpi_accounts = Transfer {
+            from: payer.to_account_info(),
+            to: account.clone(),
+        };
+        let cpi_ctx = CpiContext::new(system_program.to_account_info(), cpi_accounts);
+        system_program::transfer(cpi_ctx, rent_shortfall)?;
+    }
+    account.resize(new_len)?;
+    Ok(())
+}
+
+// Every derivative must stay redeemable from the vault, checked against the
+// post-instruction state of both accounts
+pub fn assert_solvency(
+    vault_ata: &mut InterfaceAccount<TokenAccount>,
+    derivative_mint: &mut InterfaceAccount<Mint>,
+) -> Result<()> {
+    vault_ata.reload()?;
+    derivative_mint.reload()?;
+    require!(
+        vault_ata.amount >= derivative_mint.supply,
+        TwosideErrorCodes::SolvencyViolation
+    );
+    Ok(())
+}
+
+// Emits events to the program logs (event-log feature) and through a self-CPI
+// to the event authority (event-cpi feature), which indexers can recover from
+// inner instructions even when a large transaction truncates its logs
+pub struct EventEmitter<'info> {
+    #[cfg(feature = "event-cpi")]
+    pub authority: AccountInfo<'info>,
+    #[cfg(feature = "event-cpi")]
+    pub authority_bump: u8,
+    pub _info: std::marker::PhantomData<&'info ()>,
+}
+
+impl<'info> EventEmitter<'info> {
+    #[cfg_attr(
+        not(any(feature = "event-log", feature = "event-cpi")),
+        allow(unused_variables)
+    )]
+    pub fn emit<E: anchor_lang::Event>(&self, event: &E) -> Result<()> {
+        #[cfg(feature = "event-log")]
+        emit!(*event);
+
+        #[cfg(feature = "event-cpi")]
+        {
+            // same instruction emit_cpi! builds, which needs `ctx` in scope
+            let ix_data = [anchor_lang::event::EVENT_IX_TAG_LE, &event.data()].concat();
+            let ix = anchor_lang::solana_program::instruction::Instruction::new_with_bytes(
+                crate::ID,
+                &ix_data,
+                vec![AccountMeta::new_readonly(*self.authority.key, true)],
+            );
+            anchor_lang::solana_program::program::invoke_signed(
+                &ix,
+                std::slice::from_ref(&self.authority),
+                &[&[EVENT_AUTHORITY_SEED, &[self.authority_bump]]],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Amount a transfer of `amount` delivers once the mint's transfer fee is taken
+pub fn expected_transfer_amount(mint: &AccountInfo, amount: u64, epoch: u64) -> Result<u64> {
+    if *mint.owner != spl_token_2022::ID {
+        return Ok(amount);
+    }
+
+    let mint_data = mint.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+    let transfer_fee = match mint_state.get_extension::<TransferFeeConfig>() {
+        Ok(transfer_fee_config) => transfer_fee_config
+            .calculate_epoch_fee(epoch, amount)
+            .ok_or(TwosideErrorCodes::Overflow)?,
+        Err(_) => 0,
+    };
+    Ok(amount
+        .checked_sub(transfer_fee)
+        .ok_or(TwosideErrorCodes::Overflow)?)
+}
+
+pub fn validate_mint_extensions(mint: &AccountInfo) -> Result<()> {
+    if *mint.owner != spl_token_2022::ID {
+        return Ok(());
+    }
+
+    let mint_data = mint.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+
+    // extensions newer than this program's token-2022 version do not parse,
+    // Pausable among them, which would let its authority halt the vault
+    let extensions = mint_state
+        .get_extension_types()
+        .map_err(|_| TwosideErrorCodes::UnsupportedMintExtension)?;
+    for extension in extensions {
+        match extension {
+            // either lets a third party move vault funds or blocks the vault
+            // from ever paying out, breaking the 1:1 backing of derivatives
+            ExtensionType::PermanentDelegate | ExtensionType::NonTransferable => {
+                return err!(TwosideErrorCodes::UnsupportedMintExtension);
+            }
+            // an authority can point a hook-less mint at a program later
+            ExtensionType::TransferHook => {
+                let transfer_hook = mint_state.get_extension::<TransferHook>()?;
+                require!(
+                    Option::<Pubkey>::from(transfer_hook.program_id).is_none()
+                        && Option::<Pubkey>::from(transfer_hook.authority).is_none(),
+                    TwosideErrorCodes::UnsupportedMintExtension
+                );
+            }
+            // vault and fee vault accounts would be created frozen
+            ExtensionType::DefaultAccountState => {
+                let default_state = mint_state.get_extension::<DefaultAccountState>()?;
+                require!(
+                    default_state.state != AccountState::Frozen as u8,
+                    TwosideErrorCodes::UnsupportedMintExtension
+                );
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+// Accounts and details shared by the fee transfers of one lock or unlock
+pub struct FeeContext<'a, 'info> {
+    pub token_mint: &'a InterfaceAccount<'info, Mint>,
+    pub leg: FeeLeg,
+    pub timestamp: i64,
+    pub global_info: &'a GlobalInfo,
+    pub signer: &'a Signer<'info>,
+    pub system_program: &'a Program<'info, System>,
+    pub token_program: &'a Interface<'info, TokenInterface>,
+    pub vault_authority: &'a UncheckedAccount<'info>,
+    pub vault_authority_bump: u8,
+    pub vault_ata: &'a InterfaceAccount<'info, TokenAccount>,
+    pub events: &'a EventEmitter<'info>,
+}
+
+pub fn pay_referral_fee<'info>(
+    fee_context: &FeeContext<'_, 'info>,
+    fee: u64,
+    fee_lamports: u64,
+    volume: u64,
+    referrer: &UncheckedAccount<'info>,
+    referrer_token_account: &InterfaceAccount<'info, TokenAccount>,
+    referrer_stats: &mut Account<'info, ReferrerStats>,
+) -> Result<(u64, u64)> {
+    let referral_share = |fee: u64| -> Result<u64> {
+        Ok(fee
+            .checked_mul(fee_context.global_info.referral_fee_bps as u64)
+            .ok_or(TwosideErrorCodes::Overflow)?
+            / BASIS_POINTS_DIVIDER)
+    };
+    let referral_fee = referral_share(fee)?;
+    let referral_lamports = referral_share(fee_lamports)?;
+
+    let token_mint = fee_context.token_mint;
+    let mint_key = token_mint.key();
+    if referral_fee > 0 {
+        let seeds: &[&[u8]] = &[
+            VAULT_AUTHORITY_STATIC_SEED,
+            mint_key.as_ref(),
+            &[fee_context.vault_authority_bump],
+        ];
+        let signer_slice: &[&[&[u8]]] = &[seeds];
+
+        let cpi_accounts = TransferChecked {
+            mint: token_mint.to_account_info(),
+            from: fee_context.vault_ata.to_account_info(),
+            to: referrer_token_account.to_account_info(),
+            authority: fee_context.vault_authority.to_account_info(),
+        };
+        let cpi_program = fee_context.token_program.to_account_info();
+        let cpi_context = CpiContext::new(cpi_program, cpi_accounts).with_signer(signer_slice);
+        transfer_checked(cpi_context, referral_fee, token_mint.decimals)?;
+    }
+    if referral_lamports > 0 {
+        let cpi_accounts = Transfer {
+            from: fee_context.signer.to_account_info(),
+            to: referrer.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(fee_context.system_program.to_account_info(), cpi_accounts);
+        system_program::transfer(cpi_ctx, referral_lamports)?;
+    }
+
+    // stats are informational, saturate rather than block a lock or unlock
+    referrer_stats.referrer = referrer.key();
+    referrer_stats.original_mint = mint_key;
+    referrer_stats.referral_count = referrer_stats.referral_count.saturating_add(1);
+    referrer_stats.referred_volume = referrer_stats.referred_volume.saturating_add(volume);
+    referrer_stats.fees_earned = referrer_stats.fees_earned.saturating_add(referral_fee);
+    referrer_stats.lamports_earned = referrer_stats
+        .lamports_earned
+        .saturating_add(referral_lamports);
+
+    fee_context.events.emit(&ReferralFeePaid {
+        referrer: referrer.key(),
+        token: mint_key,
+        leg: fee_context.leg,
+        volume,
+        fee,
+        referral_fee,
+        fee_lamports,
+        referral_lamports,
+        timestamp: fee_context.timestamp,
+    })?;
+    Ok((referral_fee, referral_lamports))
+}
+
+pub fn split_fee(fee: u64, global_info: &GlobalInfo) -> Result<Vec<u64>> {
+    let mut amounts = global_info
+        .fee_recipients
+        .iter()
+        .map(|recipient| {
+            // weight_bps <= 10_000, so the share fits back in u64
+            let share = fee as u128 * recipient.weight_bps as u128 / BASIS_POINTS_DIVIDER as u128;
+            u64::try_from(share).map_err(|_| TwosideErrorCodes::Overflow.into())
+        })
+        .collect::<Result<Vec<u64>>>()?;
+
+    // every share rounds down, hand the dust to one recipient so the fee
+    // vault holds exactly what its balances add up to
+    let remainder = fee
+        .checked_sub(amounts.iter().sum())
+        .ok_or(TwosideErrorCodes::Overflow)?;
+    amounts[global_info.fee_remainder_index as usize] += remainder;
+    require!(
+        amounts.iter().sum::<u64>() == fee,
+        TwosideErrorCodes::FeeDistributionMismatch
+    );
+    Ok(amounts)
+}
+
+pub fn collect_sol_fee<'info>(
+    fee_context: &FeeContext<'_, 'info>,
+    fee_lamports: u64,
+    fee_vault: &mut Account<'info, FeeVault>,
+) -> Result<()> {
+    // exempt or free operations accrue nothing, so indexers see no event
+    if fee_lamports == 0 {
+        return Ok(());
+    }
+    let global_info = fee_context.global_info;
+    let cpi_accounts = Transfer {
+        from: fee_context.signer.to_account_info(),
+        to: fee_vault.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(fee_context.system_program.to_account_info(), cpi_accounts);
+    system_program::transfer(cpi_ctx, fee_lamports)?;
+
+    let fee_recipients = &global_info.fee_recipients;
+    let amounts = split_fee(fee_lamports, global_info)?;
+    let mut shares = Vec::with_capacity(fee_recipients.len());
+    for (recipient, lamports) in fee_recipients.iter().zip(amounts) {
+        fee_vault.credit(recipient.wallet, 0, lamports)?;
+        shares.push(FeeShare {
+            wallet: recipient.wallet,
+            amount: lamports,
+        });
+    }
+
+    fee_context.events.emit(&SolFeeAccrued {
+        token: fee_context.token_mint.key(),
+        leg: fee_context.leg,
+        fee_lamports,
+        shares,
+        timestamp: fee_context.timestamp,
+    })?;
+    Ok(())
+}
+
+pub fn collect_fee<'info>(
+    fee_context: &FeeContext<'_, 'info>,
+    fee: u64,
+    fee_vault: &mut Account<'info, FeeVault>,
+    fee_vault_ata: &mut InterfaceAccount<'info, TokenAccount>,
+) -> Result<()> {
+    // exempt or free operations accrue nothing, so indexers see no event
+    if fee == 0 {
+        return Ok(());
+    }
+    let token_mint = fee_context.token_mint;
+    let global_info = fee_context.global_info;
+    let mint_key = token_mint.key();
+    let seeds: &[&[u8]] = &[
+        VAULT_AUTHORITY_STATIC_SEED,
+        mint_key.as_ref(),
+        &[fee_context.vault_authority_bump],
+    ];
+    let signer_slice: &[&[&[u8]]] = &[seeds];
+
+    let fee_vault_balance_before = fee_vault_ata.amount;
+    let cpi_accounts = TransferChecked {
+        mint: token_mint.to_account_info(),
+        from: fee_context.vault_ata.to_account_info(),
+        to: fee_vault_ata.to_account_info(),
+        authority: fee_context.vault_authority.to_account_info(),
+    };
+    let cpi_program = fee_context.token_program.to_account_info();
+    let cpi_context = CpiContext::new(cpi_program, cpi_accounts).with_signer(signer_slice);
+    transfer_checked(cpi_context, fee, token_mint.decimals)?;
+
+    // transfer-fee mints withhold part of the move, only credit what arrived
+    fee_vault_ata.reload()?;
+    let collected = fee_vault_ata
+        .amount
+        .checked_sub(fee_vault_balance_before)
+        .ok_or(TwosideErrorCodes::Overflow)?;
+
+    let fee_recipients = &global_info.fee_recipients;
+    let amounts = split_fee(collected, global_info)?;
+    let mut shares = Vec::with_capacity(fee_recipients.len());
+    for (recipient, amount) in fee_recipients.iter().zip(amounts) {
+        fee_vault.credit(recipient.wallet, amount, 0)?;
+        shares.push(FeeShare {
+            wallet: recipient.wallet,
+            amount,
+        });
+    }
+
+    fee_context.events.emit(&FeeAccrued {
+        token: mint_key,
+        leg: fee_context.leg,
+        fee,
+        shares,
+        timestamp: fee_context.timestamp,
+    })?;
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct InitializeProgram<'info> {
+    pub system_program: Program<'info, System>,
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        constraint = program.programdata_address()? == Some(program_data.key())
+        @ TwosideErrorCodes::InvalidProgramData
+    )]
+    pub program: Program<'info, crate::program::Twoside>,
+    #[account(
+        constraint = program_data.upgrade_authority_address == Some(signer.key())
+        @ TwosideErrorCodes::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(
+        init,
+        seeds = [GLOBAL_INFO_STATIC_SEED],
+        bump,
+        payer = signer,
+        space = 8 + GlobalInfo::LEN,
+    )]
+    pub global_info: Account<'info, GlobalInfo>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct UpdateGlobalConfig<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [GLOBAL_INFO_STATIC_SEED],
+        bump,
+        has_one = admin @ TwosideErrorCodes::Unauthorized,
+        constraint = global_info.is_initialized
+        @ ProgramError::UninitializedAccount
+    )]
+    pub global_info: Account<'info, GlobalInfo>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct ProposeAdmin<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [GLOBAL_INFO_STATIC_SEED],
+        bump,
+        has_one = admin @ TwosideErrorCodes::Unauthorized,
+        constraint = global_info.is_initialized
+        @ ProgramError::UninitializedAccount
+    )]
+    pub global_info: Account<'info, GlobalInfo>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct AcceptAdmin<'info> {
+    pub pending_admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [GLOBAL_INFO_STATIC_SEED],
+        bump,
+        has_one = pending_admin @ TwosideErrorCodes::Unauthorized,
+        constraint = global_info.is_initialized
+        @ ProgramError::UninitializedAccount
+    )]
+    pub global_info: Account<'info, GlobalInfo>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct CancelAdminTransfer<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [GLOBAL_INFO_STATIC_SEED],
+        bump,
+        has_one = admin @ TwosideErrorCodes::Unauthorized,
+        constraint = global_info.is_initialized
+        @ ProgramError::UninitializedAccount
+    )]
+    pub global_info: Account<'info, GlobalInfo>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct SetGlobalPause<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [GLOBAL_INFO_STATIC_SEED],
+        bump,
+        has_one = admin @ TwosideErrorCodes::Unauthorized,
+        constraint = global_info.is_initialized
+        @ ProgramError::UninitializedAccount
+    )]
+    pub global_info: Account<'info, GlobalInfo>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct SetTokenPause<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [GLOBAL_INFO_STATIC_SEED],
+        bump,
+        has_one = admin @ TwosideErrorCodes::Unauthorized,
+        constraint = global_info.is_initialized
+        @ ProgramError::UninitializedAccount
+    )]
+    pub global_info: Account<'info, GlobalInfo>,
+    #[account(
+        mut,
+        seeds = [
+            TOKEN_INFO_STATIC_SEED,
+            token_info.original_mint.as_ref()
+        ],
+        bump,
+        constraint = token_info.is_initialized
+        @ ProgramError::UninitializedAccount
+    )]
+    pub token_info: Account<'info, TokenInfo>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct SetTokenFeeOverride<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [GLOBAL_INFO_STATIC_SEED],
+        bump,
+        has_one = admin @ TwosideErrorCodes::Unauthorized,
+        constraint = global_info.is_initialized
+        @ ProgramError::UninitializedAccount
+    )]
+    pub global_info: Account<'info, GlobalInfo>,
+    #[account(
+        mut,
+        seeds = [
+            TOKEN_INFO_STATIC_SEED,
+            token_info.original_mint.as_ref()
+        ],
+        bump,
+        constraint = token_info.is_initialized
+        @ ProgramError::UninitializedAccount
+    )]
+    pub token_info: Account<'info, TokenInfo>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct SetTokenSolFee<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [GLOBAL_INFO_STATIC_SEED],
+        bump,
+        has_one = admin @ TwosideErrorCodes::Unauthorized,
+        constraint = global_info.is_initialized
+        @ ProgramError::UninitializedAccount
+    )]
+    pub global_info: Account<'info, GlobalInfo>,
+    #[account(
+        mut,
+        seeds = [
+            TOKEN_INFO_STATIC_SEED,
+            token_info.original_mint.as_ref()
+        ],
+        bump,
+        constraint = token_info.is_initialized
+        @ ProgramError::UninitializedAccount
+    )]
+    pub token_info: Account<'info, TokenInfo>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct MigrateGlobalInfo<'info> {
+    pub system_program: Program<'info, System>,
+
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    // not `program`, #[event_cpi] adds an account by that name
+    #[account(
+        constraint = twoside_program.programdata_address()? == Some(program_data.key())
+        @ TwosideErrorCodes::InvalidProgramData
+    )]
+    pub twoside_program: Program<'info, crate::program::Twoside>,
+    #[account(
+        constraint = program_data.upgrade_authority_address == Some(signer.key())
+        @ TwosideErrorCodes::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    /// CHECK: GlobalInfo at a legacy layout, read by read_legacy_account
+    #[account(
+        mut,
+        seeds = [GLOBAL_INFO_STATIC_SEED],
+        bump,
+    )]
+    pub global_info: UncheckedAccount<'info>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct MigrateTokenInfo<'info> {
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    #[account(mint::token_program = token_program)]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+    /// CHECK: TokenInfo at a legacy layout, read by read_legacy_account
+    #[account(
+        mut,
+        seeds = [
+            TOKEN_INFO_STATIC_SEED,
+            token_mint.key().as_ref()
+        ],
+        bump,
+    )]
+    pub token_info: UncheckedAccount<'info>,
+    #[account(
+        seeds = [
+            VAULT_AUTHORITY_STATIC_SEED,
+            token_mint.key().as_ref()
+        ],
+        bump,
+    )]
+    /// CHECK: Token Vault's Authority.
+    pub vault_authority: UncheckedAccount<'info>,
+    #[account(
+        associated_token::mint = token_mint,
+        associated_token::authority = vault_authority,
+        associated_token::token_program = token_program,
+    )]
+    pub vault_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        init,
+        seeds = [FEE_VAULT_STATIC_SEED, token_mint.key().as_ref()],
+        bump,
+        payer = signer,
+        space = 8 + FeeVault::LEN,
+    )]
+    pub fee_vault: Box<Account<'info, FeeVault>>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = token_mint,
+        associated_token::authority = fee_vault,
+        associated_token::token_program = token_program,
+    )]
+    pub fee_vault_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct SetTokenLabel<'info> {
+    pub system_program: Program<'info, System>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [GLOBAL_INFO_STATIC_SEED],
+        bump,
+        has_one = admin @ TwosideErrorCodes::Unauthorized,
+        constraint = global_info.is_initialized
+        @ ProgramError::UninitializedAccount
+    )]
+    pub global_info: Account<'info, GlobalInfo>,
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init_if_needed,
+        seeds = [TOKEN_LABEL_STATIC_SEED, token_mint.key().as_ref()],
+        bump,
+        payer = admin,
+        space = 8 + TokenLabel::LEN,
+    )]
+    pub token_label: Account<'info, TokenLabel>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct SetTokenListStatus<'info> {
+    pub system_program: Program<'info, System>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [GLOBAL_INFO_STATIC_SEED],
+        bump,
+        has_one = admin @ TwosideErrorCodes::Unauthorized,
+        constraint = global_info.is_initialized
+        @ ProgramError::UninitializedAccount
+    )]
+    pub global_info: Account<'info, GlobalInfo>,
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init_if_needed,
+        seeds = [TOKEN_LIST_ENTRY_STATIC_SEED, token_mint.key().as_ref()],
+        bump,
+        payer = admin,
+        space = 8 + TokenListEntry::LEN,
+    )]
+    pub token_list_entry: Account<'info, TokenListEntry>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+#[instruction(subject: Pubkey)]
+pub struct SetFeeExemption<'info> {
+    pub system_program: Program<'info, System>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [GLOBAL_INFO_STATIC_SEED],
+        bump,
+        has_one = admin @ TwosideErrorCodes::Unauthorized,
+        constraint = global_info.is_initialized
+        @ ProgramError::UninitializedAccount
+    )]
+    pub global_info: Account<'info, GlobalInfo>,
+    #[account(
+        init_if_needed,
+        seeds = [FEE_EXEMPTION_STATIC_SEED, subject.as_ref()],
+        bump,
+        payer = admin,
+        space = 8 + FeeExemption::LEN,
+    )]
+    pub fee_exemption: Account<'info, FeeExemption>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+#[instruction(referrer: Pubkey)]
+pub struct SetReferrerStatus<'info> {
+    pub system_program: Program<'info, System>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [GLOBAL_INFO_STATIC_SEED],
+        bump,
+        has_one = admin @ TwosideErrorCodes::Unauthorized,
+        constraint = global_info.is_initialized
+        @ ProgramError::UninitializedAccount
+    )]
+    pub global_info: Account<'info, GlobalInfo>,
+    #[account(
+        init_if_needed,
+        seeds = [REFERRER_ENTRY_STATIC_SEED, referrer.as_ref()],
+        bump,
+        payer = admin,
+        space = 8 + ReferrerEntry::LEN,
+    )]
+    pub referrer_entry: Account<'info, ReferrerEntry>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct SetFeeTiers<'info> {
+    pub system_program: Program<'info, System>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        seeds = [GLOBAL_INFO_STATIC_SEED],
+        bump,
+        has_one = admin @ TwosideErrorCodes::Unauthorized,
+        constraint = global_info.is_initialized
+        @ ProgramError::UninitializedAccount
+    )]
+    pub global_info: Account<'info, GlobalInfo>,
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        seeds = [TOKEN_INFO_STATIC_SEED, token_mint.key().as_ref()],
+        bump,
+        constraint = token_info.version == TOKEN_INFO_VERSION
+        @ TwosideErrorCodes::AccountNotMigrated
+    )]
+    pub token_info: Box<Account<'info, TokenInfo>>,
+    #[account(
+        init_if_needed,
+        seeds = [FEE_TIERS_STATIC_SEED, token_mint.key().as_ref()],
+        bump,
+        payer = admin,
+        space = 8 + FeeTierSchedule::LEN,
+    )]
+    pub fee_tiers: Account<'info, FeeTierSchedule>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct RegisterToken<'info> {
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub derivative_token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    /// CHECK: This is the Metaplex Token Metadata program, only needed when
+    /// the derivative is created with Metaplex metadata
+    pub mpl_token_metadata_program: Option<UncheckedAccount<'info>>,
+    pub rent: Sysvar<'info, Rent>,
+
+    #[account(
+        mint::token_program = token_program,
+        constraint = token_mint.is_initialized
+        @ ProgramError::UninitializedAccount
+    )]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+    /// CHECK: Metaplex metadata PDA of the token being registered, checked
+    /// and only read when it exists in resolve_source_metadata
+    pub token_metadata: UncheckedAccount<'info>,
+    /// CHECK: Admin-registered label PDA, only read when it exists so the
+    /// registrant cannot skip it
+    #[account(
+        seeds = [TOKEN_LABEL_STATIC_SEED, token_mint.key().as_ref()],
+        bump,
+    )]
+    pub token_label: UncheckedAccount<'info>,
+    /// CHECK: Admin-managed allow/deny entry PDA, only read when it exists
+    #[account(
+        seeds = [TOKEN_LIST_ENTRY_STATIC_SEED, token_mint.key().as_ref()],
+        bump,
+    )]
+    pub token_list_entry: UncheckedAccount<'info>,
+
+    /// CHECK: Derivative Token's Mint Authority.
+    #[account(
+        seeds = [
+        DERIVATIVE_AUTHORITY_SEED,
+        token_mint.key().as_ref()
+        ], bump
+    )]
+    pub derivative_authority: UncheckedAccount<'info>,
+    /// CHECK: Derivative Token's Mint, created in the handler with the
+    /// layout of the configured metadata mode.
+    #[account(
+        mut,
+        seeds = [DERIVATIVE_MINT_STATIC_SEED, token_mint.key().as_ref()],
+        bump
+    )]
+    pub derivative_mint: UncheckedAccount<'info>,
+    /// CHECK: Metaplex Metadata account PDA, derived from mint and Metaplex program ID.
+    /// Only needed when the derivative is created with Metaplex metadata
+    #[account(mut)]
+    pub derivative_metadata: Option<UncheckedAccount<'info>>,
+
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    #[account(
+        init,
+        seeds = [
+            TOKEN_INFO_STATIC_SEED,
+            token_mint.key().as_ref()
+        ],
+        bump,
+        payer = signer,
+        space = 8 + TokenInfo::LEN,
+    )]
+    pub token_info: Box<Account<'info, TokenInfo>>,
+
+    #[account(
+        seeds = [
+            VAULT_AUTHORITY_STATIC_SEED,
+            token_mint.key().as_ref()
+        ],
+        bump,
+    )]
+    /// CHECK: Token Vault's Authority.
+    pub vault_authority: UncheckedAccount<'info>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = token_mint,
+        associated_token::authority = vault_authority,
+        associated_token::token_program = token_program,
+    )]
+    pub vault_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        init,
+        seeds = [FEE_VAULT_STATIC_SEED, token_mint.key().as_ref()],
+        bump,
+        payer = signer,
+        space = 8 + FeeVault::LEN,
+    )]
+    pub fee_vault: Box<Account<'info, FeeVault>>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = token_mint,
+        associated_token::authority = fee_vault,
+        associated_token::token_program = token_program,
+    )]
+    pub fee_vault_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        seeds = [GLOBAL_INFO_STATIC_SEED],
+        bump,
+        constraint = global_info.is_initialized
+        @ ProgramError::UninitializedAccount
+    )]
+    pub global_info: Box<Account<'info, GlobalInfo>>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct Lock<'info> {
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub derivative_token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+
+    /// CHECK: Instructions sysvar must be passed in
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub sysvar_instructions: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        mint::token_program = token_program,
+        constraint = token_mint.is_initialized
+        @ ProgramError::UninitializedAccount
+    )]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+    /// CHECK: Admin-managed allow/deny entry PDA, only read when it exists
+    #[account(
+        seeds = [TOKEN_LIST_ENTRY_STATIC_SEED, token_mint.key().as_ref()],
+        bump,
+    )]
+    pub token_list_entry: UncheckedAccount<'info>,
+
+    /// CHECK: Derivative Token's Mint Authority.
+    #[account(
+        seeds = [
+        DERIVATIVE_AUTHORITY_SEED,
+        token_mint.key().as_ref()
+        ], bump
+    )]
+    pub derivative_authority: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        mint::authority = derivative_authority,
+        mint::token_program = derivative_token_program,
+        seeds = [DERIVATIVE_MINT_STATIC_SEED, token_mint.key().as_ref()],
+        bump
+    )]
+    pub derivative_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = signer,
+        token::token_program = token_program,
+    )]
+    pub signer_token_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = derivative_mint,
+        associated_token::authority = signer,
+        associated_token::token_program = derivative_token_program,
+    )]
+    pub signer_derivative_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + UserVolume::LEN,
+        seeds = [
+            USER_VOLUME_STATIC_SEED,
+            signer.key().as_ref(),
+            token_mint.key().as_ref()
+        ],
+        bump,
+    )]
+    pub user_volume: Option<Box<Account<'info, UserVolume>>>,
+    /// CHECK: Admin-managed fee tier schedule PDA, only read when it exists
+    #[account(
+        seeds = [FEE_TIERS_STATIC_SEED, token_mint.key().as_ref()],
+        bump,
+    )]
+    pub fee_tiers: UncheckedAccount<'info>,
+    /// CHECK: Admin-managed fee exemption PDA for the signer, only read when
+    /// it exists
+    #[account(
+        seeds = [FEE_EXEMPTION_STATIC_SEED, signer.key().as_ref()],
+        bump,
+    )]
+    pub signer_fee_exemption: UncheckedAccount<'info>,
+    /// CHECK: Fee exemption PDA for the program invoking this one by CPI,
+    /// matched against the instructions sysvar in the handler
+    pub caller_fee_exemption: Option<UncheckedAccount<'info>>,
+    /// CHECK: Pyth price update for the token, required by a priced SOL fee
+    /// and matched against it in the handler
+    pub token_price_feed: Option<UncheckedAccount<'info>>,
+    /// CHECK: Pyth price update for SOL, same as token_price_feed
+    pub sol_price_feed: Option<UncheckedAccount<'info>>,
+    /// CHECK: Optional wallet of the frontend or aggregator that routed the
+    /// transaction, paid a share of the fee
+    #[account(mut)]
+    pub referrer: Option<UncheckedAccount<'info>>,
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = referrer,
+        token::token_program = token_program,
+    )]
+    pub referrer_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + ReferrerStats::LEN,
+        seeds = [
+            REFERRER_STATS_STATIC_SEED,
+            referrer.as_ref().map(|referrer| referrer.key()).unwrap_or_default().as_ref(),
+            token_mint.key().as_ref()
+        ],
+        bump,
+    )]
+    pub referrer_stats: Option<Box<Account<'info, ReferrerStats>>>,
+    /// CHECK: Admin-managed allow-list entry PDA for the referrer, only read
+    /// when it exists
+    #[account(
+        seeds = [
+            REFERRER_ENTRY_STATIC_SEED,
+            referrer.as_ref().map(|referrer| referrer.key()).unwrap_or_default().as_ref()
+        ],
+        bump,
+    )]
+    pub referrer_entry: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        mut,
+        seeds = [
+            TOKEN_INFO_STATIC_SEED,
+            token_mint.key().as_ref()
+        ],
+        bump,
+        constraint = token_info.version == TOKEN_INFO_VERSION
+        @ TwosideErrorCodes::AccountNotMigrated
+    )]
+    pub token_info: Box<Account<'info, TokenInfo>>,
+
+    #[account(
+        seeds = [
+            VAULT_AUTHORITY_STATIC_SEED,
+            token_mint.key().as_ref()
+        ],
+        bump,
+    )]
+    /// CHECK: Token Vault's Authority.
+    pub vault_authority: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = vault_authority,
+        associated_token::token_program = token_program,
+    )]
+    pub vault_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_STATIC_SEED, token_mint.key().as_ref()],
+        bump,
+    )]
+    pub fee_vault: Box<Account<'info, FeeVault>>,
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = fee_vault,
+        associated_token::token_program = token_program,
+    )]
+    pub fee_vault_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [GLOBAL_INFO_STATIC_SEED],
+        bump,
+        constraint = global_info.is_initialized
+        @ ProgramError::UninitializedAccount,
+        constraint = global_info.version == GLOBAL_INFO_VERSION
+        @ TwosideErrorCodes::AccountNotMigrated
+    )]
+    pub global_info: Box<Account<'info, GlobalInfo>>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct Unlock<'info> {
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub derivative_token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+
+    /// CHECK: Instructions sysvar must be passed in
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub sysvar_instructions: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        mint::token_program = token_program,
+        constraint = token_mint.is_initialized
+        @ ProgramError::UninitializedAccount
+    )]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: Derivative Token's Mint Authority.
+    #[account(
+        seeds = [
+        DERIVATIVE_AUTHORITY_SEED,
+        token_mint.key().as_ref()
+        ], bump
+    )]
+    pub derivative_authority: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        mint::decimals = token_mint.decimals,
+        mint::authority = derivative_authority,
+        mint::freeze_authority = derivative_authority,
+        mint::token_program = derivative_token_program,
+        seeds = [DERIVATIVE_MINT_STATIC_SEED, token_mint.key().as_ref()],
+        bump
+    )]
+    pub derivative_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = signer,
+        token::token_program = token_program,
+    )]
+    pub signer_token_ata: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        associated_token::mint = derivative_mint,
+        associated_token::authority = signer,
+        associated_token::token_program = derivative_token_program,
+    )]
+    pub signer_derivative_ata: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + UserVolume::LEN,
+        seeds = [
+            USER_VOLUME_STATIC_SEED,
+            signer.key().as_ref(),
             token_mint.key().as_ref()
         ],
         bump,
     )]
-    /// CHECK: Token Vault's Authority.
-    pub vault_authority: UncheckedAccount<'info>,
+    pub user_volume: Option<Box<Account<'info, UserVolume>>>,
+    /// CHECK: Admin-managed fee tier schedule PDA, only read when it exists
+    #[account(
+        seeds = [FEE_TIERS_STATIC_SEED, token_mint.key().as_ref()],
+        bump,
+    )]
+    pub fee_tiers: UncheckedAccount<'info>,
+    /// CHECK: Admin-managed fee exemption PDA for the signer, only read when
+    /// it exists
+    #[account(
+        seeds = [FEE_EXEMPTION_STATIC_SEED, signer.key().as_ref()],
+        bump,
+    )]
+    pub signer_fee_exemption: UncheckedAccount<'info>,
+    /// CHECK: Fee exemption PDA for the program invoking this one by CPI,
+    /// matched against the instructions sysvar in the handler
+    pub caller_fee_exemption: Option<UncheckedAccount<'info>>,
+    /// CHECK: Pyth price update for the token, required by a priced SOL fee
+    /// and matched against it in the handler
+    pub token_price_feed: Option<UncheckedAccount<'info>>,
+    /// CHECK: Pyth price update for SOL, same as token_price_feed
+    pub sol_price_feed: Option<UncheckedAccount<'info>>,
+    /// CHECK: Optional wallet of the frontend or aggregator that routed the
+    /// transaction, paid a share of the fee
+    #[account(mut)]
+    pub referrer: Option<UncheckedAccount<'info>>,
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = referrer,
+        token::token_program = token_program,
+    )]
+    pub referrer_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
     #[account(
         init_if_needed,
         payer = signer,
+        space = 8 + ReferrerStats::LEN,
+        seeds = [
+            REFERRER_STATS_STATIC_SEED,
+            referrer.as_ref().map(|referrer| referrer.key()).unwrap_or_default().as_ref(),
+            token_mint.key().as_ref()
+        ],
+        bump,
+    )]
+    pub referrer_stats: Option<Box<Account<'info, ReferrerStats>>>,
+    /// CHECK: Admin-managed allow-list entry PDA for the referrer, only read
+    /// when it exists
+    #[account(
+        seeds = [
+            REFERRER_ENTRY_STATIC_SEED,
+            referrer.as_ref().map(|referrer| referrer.key()).unwrap_or_default().as_ref()
+        ],
+        bump,
+    )]
+    pub referrer_entry: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        mut,
+        seeds = [
+            TOKEN_INFO_STATIC_SEED,
+            token_mint.key().as_ref()
+        ],
+        bump,
+        constraint = token_info.original_mint == token_mint.key() &&
+        token_info.is_initialized,
+        constraint = token_info.version == TOKEN_INFO_VERSION
+        @ TwosideErrorCodes::AccountNotMigrated
+    )]
+    pub token_info: Box<Account<'info, TokenInfo>>,
+
+    #[account(
+        seeds = [
+            VAULT_AUTHORITY_STATIC_SEED,
+            token_mint.key().as_ref()
+        ],
+        bump,
+    )]
+    /// CHECK: Token Vault's Authority.
+    pub vault_authority: UncheckedAccount<'info>,
+    #[account(
+        mut,
         associated_token::mint = token_mint,
         associated_token::authority = vault_authority,
+        associated_token::token_program = token_program,
+    )]
+    pub vault_ata: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_STATIC_SEED, token_mint.key().as_ref()],
+        bump,
+    )]
+    pub fee_vault: Box<Account<'info, FeeVault>>,
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = fee_vault,
+        associated_token::token_program = token_program,
     )]
-    pub vault_ata: Box<Account<'info, TokenAccount>>,
+    pub fee_vault_ata: Box<InterfaceAccount<'info, TokenAccount>>,
 
     #[account(
         mut,
         seeds = [GLOBAL_INFO_STATIC_SEED],
         bump,
         constraint = global_info.is_initialized
-        @ ProgramError::UninitializedAccount
+        @ ProgramError::UninitializedAccount,
+        constraint = global_info.version == GLOBAL_INFO_VERSION
+        @ TwosideErrorCodes::AccountNotMigrated
     )]
     pub global_info: Box<Account<'info, GlobalInfo>>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct VerifySolvency<'info> {
+    pub token_program: Interface<'info, TokenInterface>,
+    pub derivative_token_program: Interface<'info, TokenInterface>,
 
+    #[account(mint::token_program = token_program)]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
     #[account(
-        mut,
-        token::mint = token_mint,
-        constraint = founder_ata.owner == global_info.founder_wallet
+        seeds = [
+            TOKEN_INFO_STATIC_SEED,
+            token_mint.key().as_ref()
+        ],
+        bump,
+        constraint = token_info.is_initialized
+        @ ProgramError::UninitializedAccount
+    )]
+    pub token_info: Box<Account<'info, TokenInfo>>,
+    #[account(
+        address = token_info.derivative_mint @ TwosideErrorCodes::InvalidDerivativeAddress,
+        mint::token_program = derivative_token_program,
     )]
-    pub founder_ata: Box<Account<'info, TokenAccount>>,
+    pub derivative_mint: Box<InterfaceAccount<'info, Mint>>,
     #[account(
-        mut,
-        token::mint = token_mint,
-        constraint = developer_ata.owner == global_info.developer_wallet
+        seeds = [
+            VAULT_AUTHORITY_STATIC_SEED,
+            token_mint.key().as_ref()
+        ],
+        bump,
+    )]
+    /// CHECK: Token Vault's Authority.
+    pub vault_authority: UncheckedAccount<'info>,
+    #[account(
+        associated_token::mint = token_mint,
+        associated_token::authority = vault_authority,
+        associated_token::token_program = token_program,
     )]
-    pub developer_ata: Box<Account<'info, TokenAccount>>,
+    pub vault_ata: Box<InterfaceAccount<'info, TokenAccount>>,
 }
 
+#[cfg_attr(feature = "event-cpi", event_cpi)]
 #[derive(Accounts)]
-pub struct Unlock<'info> {
+pub struct ClaimFees<'info> {
     pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
+    pub token_program: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
 
+    #[account(mint::token_program = token_program)]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    /// CHECK: Wallet whose accrued fees are paid out, anyone may crank the
+    /// claim since funds only go to it or its canonical token account
+    #[account(mut)]
+    pub recipient: UncheckedAccount<'info>,
     #[account(
-        mut,
-        constraint = token_mint.is_initialized
-        @ ProgramError::UninitializedAccount
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = token_mint,
+        associated_token::authority = recipient,
+        associated_token::token_program = token_program,
     )]
-    pub token_mint: Box<Account<'info, Mint>>,
+    pub recipient_ata: Box<InterfaceAccount<'info, TokenAccount>>,
 
-    /// CHECK: Derivative Token's Mint Authority.
     #[account(
-        seeds = [
-        DERIVATIVE_AUTHORITY_SEED,
-        token_mint.key().as_ref()
-        ], bump
+        mut,
+        seeds = [FEE_VAULT_STATIC_SEED, token_mint.key().as_ref()],
+        bump,
     )]
-    pub derivative_authority: UncheckedAccount<'info>,
+    pub fee_vault: Box<Account<'info, FeeVault>>,
     #[account(
         mut,
-        mint::decimals = token_mint.decimals,
-        mint::authority = derivative_authority,
-        mint::freeze_authority = derivative_authority,
-        seeds = [DERIVATIVE_MINT_STATIC_SEED, token_mint.key().as_ref()],
-        bump
+        associated_token::mint = token_mint,
+        associated_token::authority = fee_vault,
+        associated_token::token_program = token_program,
     )]
-    pub derivative_mint: Box<Account<'info, Mint>>,
+    pub fee_vault_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+}
+
+pub const GLOBAL_INFO_STATIC_SEED: &[u8] = b"global_info";
+pub const TOKEN_INFO_STATIC_SEED: &[u8] = b"token_info";
+pub const VAULT_AUTHORITY_STATIC_SEED: &[u8] = b"vault_authority";
+pub const METADATA_STATIC_SEED: &[u8] = b"metadata";
+pub const DERIVATIVE_AUTHORITY_SEED: &[u8] = b"derivative_authority";
+pub const DERIVATIVE_MINT_STATIC_SEED: &[u8] = b"derivative_mint";
+pub const TOKEN_LABEL_STATIC_SEED: &[u8] = b"token_label";
+pub const TOKEN_LIST_ENTRY_STATIC_SEED: &[u8] = b"token_list_entry";
+pub const FEE_VAULT_STATIC_SEED: &[u8] = b"fee_vault";
+pub const FEE_EXEMPTION_STATIC_SEED: &[u8] = b"fee_exemption";
+pub const REFERRER_STATS_STATIC_SEED: &[u8] = b"referrer_stats";
+pub const REFERRER_ENTRY_STATIC_SEED: &[u8] = b"referrer_entry";
+pub const FEE_TIERS_STATIC_SEED: &[u8] = b"fee_tiers";
+pub const USER_VOLUME_STATIC_SEED: &[u8] = b"user_volume";
+// fixed by #[event_cpi]
+pub const EVENT_AUTHORITY_SEED: &[u8] = b"__event_authority";
+
+// Upper bound for any fee, 1000 bps = 10%
+pub const MAX_FEE_BASIS_POINTS: u64 = 1000;
+pub const BASIS_POINTS_DIVIDER: u64 = 10_000;
+
+pub const MAX_FEE_RECIPIENTS: usize = 5;
+// room for recipients dropped from the config that have not claimed yet
+pub const MAX_FEE_BALANCES: usize = 2 * MAX_FEE_RECIPIENTS;
+pub const MAX_FEE_TIERS: usize = 4;
+
+// Caps for SolFee, no lock or unlock costs more than 1 SOL and prices may be
+// at most an hour old
+pub const MAX_SOL_FEE_LAMPORTS: u64 = 1_000_000_000;
+pub const MAX_PRICE_AGE_SECONDS: u32 = 3600;
+// Widest confidence interval accepted, relative to the price
+pub const MAX_PRICE_CONF_BPS: u64 = 200;
+pub const LAMPORTS_DECIMALS: i32 = 9;
+pub const PYTH_RECEIVER_PROGRAM_ID: Pubkey = pubkey!("rec5EKMGg6MxZYaMdyBfgwp4d5rB9T1VQH5pJv5LtFJ");
+pub const PRICE_UPDATE_V2_DISCRIMINATOR: [u8; 8] = [34, 241, 35, 99, 157, 126, 244, 205];
+
+// Metaplex limits, so labels fit either metadata standard
+pub const MAX_LABEL_NAME_LENGTH: usize = 32;
+pub const MAX_LABEL_SYMBOL_LENGTH: usize = 10;
+pub const MAX_LABEL_URI_LENGTH: usize = 200;
+
+// Leading mint address characters used when a token has no metadata at all
+pub const FALLBACK_NAME_CHARS: usize = 8;
+pub const FALLBACK_SYMBOL_CHARS: usize = 4;
+
+// Layout versions, bumped whenever a field is carved out of the reserved bytes
+// or the layout otherwise changes, accounts from before versioning are v1
+pub const GLOBAL_INFO_VERSION: u8 = 2;
+pub const TOKEN_INFO_VERSION: u8 = 2;
+pub const RESERVED_ACCOUNT_BYTES: usize = 64;
+
+// Bit flags for GlobalInfo::paused and TokenInfo::paused
+pub const PAUSE_LOCK: u8 = 1 << 0;
+pub const PAUSE_UNLOCK: u8 = 1 << 1;
+pub const PAUSE_ALL: u8 = PAUSE_LOCK | PAUSE_UNLOCK;
+
+#[account]
+pub struct GlobalInfo {
+    pub version: u8,                                      // 8 / 8 = 1
+    pub is_initialized: bool,                             // 1
+    pub fee_recipients: Vec<FeeRecipient>,                // 4 + 34 * MAX_FEE_RECIPIENTS
+    pub lock_fee: FeeConfig,                              // 18
+    pub unlock_fee: FeeConfig,                            // 18
+    pub admin: Pubkey,                                    // 32
+    pub pending_admin: Pubkey,                            // 32
+    pub paused: u8,                                       // 8 / 8 = 1
+    pub derivative_metadata_mode: DerivativeMetadataMode, // 1
+    pub permissionless_registration: bool,                // 1
+    pub token_list_mode: TokenListMode,                   // 1
+    pub fee_remainder_index: u8,                          // 8 / 8 = 1
+    pub referral_fee_bps: u16,                            // 16 / 8 = 2
+    pub reserved: [u8; RESERVED_ACCOUNT_BYTES],           // 64
+}
+
+impl GlobalInfo {
+    pub const LEN: usize = 1
+        + 1
+        + 4
+        + FeeRecipient::LEN * MAX_FEE_RECIPIENTS
+        + FeeConfig::LEN * 2
+        + 32
+        + 32
+        + 1
+        + 1
+        + 1
+        + 1
+        + 1
+        + 2
+        + RESERVED_ACCOUNT_BYTES;
+
+    pub fn from_v1(legacy: GlobalInfoV1, admin: Pubkey) -> Result<GlobalInfo> {
+        // the legacy rate was fee_percentage / fee_percentage_divider
+        if legacy.fee_percentage_divider == 0 {
+            return Err(ProgramError::InvalidAccountData.into());
+        }
+        let fee_bps = legacy.fee_percentage as u64 * BASIS_POINTS_DIVIDER
+            / legacy.fee_percentage_divider as u64;
+        let fee_bps = u16::try_from(fee_bps).map_err(|_| TwosideErrorCodes::FeeTooHigh)?;
+        // the legacy fee fell back to min_fee below min_fee_for_distribution,
+        // a min_fee floor charges the same while the two are equal
+        let fee_config = FeeConfig {
+            fee_bps,
+            min_fee: legacy.min_fee as u64,
+            max_fee: u64::MAX,
+        };
+        // legacy shares are percentages of the fee
+        let share_weight = (BASIS_POINTS_DIVIDER / 100) as u16;
+        Ok(GlobalInfo {
+            version: GLOBAL_INFO_VERSION,
+            is_initialized: legacy.is_initialized,
+            fee_recipients: vec![
+                FeeRecipient {
+                    wallet: legacy.developer_wallet,
+                    weight_bps: legacy.developer_fee_share as u16 * share_weight,
+                },
+                FeeRecipient {
+                    wallet: legacy.founder_wallet,
+                    weight_bps: legacy.founder_fee_share as u16 * share_weight,
+                },
+            ],
+            lock_fee: fee_config,
+            unlock_fee: fee_config,
+            admin,
+            pending_admin: Pubkey::default(),
+            paused: 0,
+            derivative_metadata_mode: DerivativeMetadataMode::Metaplex,
+            permissionless_registration: true,
+            token_list_mode: TokenListMode::Open,
+            fee_remainder_index: 1,
+            referral_fee_bps: 0,
+            reserved: [0; RESERVED_ACCOUNT_BYTES],
+        })
+    }
+
+    pub fn config(&self) -> GlobalConfig {
+        GlobalConfig {
+            fee_recipients: self.fee_recipients.clone(),
+            lock_fee: self.lock_fee,
+            unlock_fee: self.unlock_fee,
+            derivative_metadata_mode: self.derivative_metadata_mode,
+            permissionless_registration: self.permissionless_registration,
+            token_list_mode: self.token_list_mode,
+            fee_remainder_index: self.fee_remainder_index,
+            referral_fee_bps: self.referral_fee_bps,
+        }
+    }
+
+    pub fn set_config(&mut self, config: &GlobalConfig) {
+        self.fee_recipients = config.fee_recipients.clone();
+        self.lock_fee = config.lock_fee;
+        self.unlock_fee = config.unlock_fee;
+        self.derivative_metadata_mode = config.derivative_metadata_mode;
+        self.permissionless_registration = config.permissionless_registration;
+        self.token_list_mode = config.token_list_mode;
+        self.fee_remainder_index = config.fee_remainder_index;
+        self.referral_fee_bps = config.referral_fee_bps;
+    }
+}
+
+// GlobalInfo before layouts were versioned, only read by migrate_global_info
+#[derive(AnchorDeserialize)]
+pub struct GlobalInfoV1 {
+    pub is_initialized: bool,         // 1
+    pub developer_wallet: Pubkey,     // 32
+    pub founder_wallet: Pubkey,       // 32
+    pub fee_percentage: u8,           // 8 / 8 = 1
+    pub fee_percentage_divider: u16,  // 16 / 8 = 2
+    pub min_fee_for_distribution: u8, // 8 / 8 = 1
+    pub min_fee: u8,                  // 8 / 8 = 1
+    pub developer_fee_share: u8,      // 8 / 8 = 1
+    pub founder_fee_share: u8,        // 8 / 8 = 1
+}
+
+impl GlobalInfoV1 {
+    pub const LEN: usize = 1 + 32 + 32 + 1 + 2 + 1 + 1 + 1 + 1;
+}
+
+// Admin-updatable subset of GlobalInfo
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct GlobalConfig {
+    pub fee_recipients: Vec<FeeRecipient>,
+    pub lock_fee: FeeConfig,
+    pub unlock_fee: FeeConfig,
+    pub derivative_metadata_mode: DerivativeMetadataMode,
+    pub permissionless_registration: bool,
+    pub token_list_mode: TokenListMode,
+    // recipient that receives the rounding remainder of the split
+    pub fee_remainder_index: u8,
+    // share of each fee paid to the referrer, taken before the recipient split
+    pub referral_fee_bps: u16,
+}
+
+// Where metadata of newly created derivative mints is stored
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DerivativeMetadataMode {
+    // Metaplex metadata account, derivative under either token program
+    Metaplex,
+    // Token-2022 derivative with MetadataPointer + TokenMetadata on the mint
+    Token2022,
+}
+
+// Which mints may be registered and locked
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TokenListMode {
+    // any mint, the registry is ignored
+    Open,
+    // only mints listed as allowed
+    Allowlist,
+    // any mint not listed as denied
+    Denylist,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TokenListStatus {
+    Unlisted,
+    Allowed,
+    Denied,
+}
+
+#[account]
+pub struct TokenInfo {
+    pub version: u8,                            // 8 / 8 = 1
+    pub is_initialized: bool,                   // 1
+    pub original_mint: Pubkey,                  // 32
+    pub derivative_mint: Pubkey,                // 32
+    pub paused: u8,                             // 8 / 8 = 1
+    pub lock_fee_override: Option<FeeConfig>,   // 1 + 18
+    pub unlock_fee_override: Option<FeeConfig>, // 1 + 18
+    pub sol_fee: Option<SolFee>,                // 1 + 69
+    // vault balance is total_locked - total_unlocked, fees are in the locked
+    // token and leave SOL fees out
+    pub total_locked: u64,                      // 64 / 8 = 8
+    pub total_unlocked: u64,                    // 64 / 8 = 8
+    pub cumulative_fees: u64,                   // 64 / 8 = 8
+    pub lock_count: u64,                        // 64 / 8 = 8
+    pub unlock_count: u64,                      // 64 / 8 = 8
+    pub created_at: i64,                        // 64 / 8 = 8
+    pub reserved: [u8; RESERVED_ACCOUNT_BYTES], // 64
+}
+
+impl TokenInfo {
+    pub const LEN: usize = 1
+        + 1
+        + 32
+        + 32
+        + 1
+        + (1 + FeeConfig::LEN) * 2
+        + 1
+        + SolFee::LEN
+        + 8 * 6
+        + RESERVED_ACCOUNT_BYTES;
+
+    // the legacy account kept no books, they start from `vault_balance` so it
+    // still equals total_locked - total_unlocked, and the migration stands in
+    // for the unrecorded creation time
+    pub fn from_v1(legacy: TokenInfoV1, vault_balance: u64, migrated_at: i64) -> TokenInfo {
+        TokenInfo {
+            version: TOKEN_INFO_VERSION,
+            is_initialized: legacy.is_initialized,
+            original_mint: legacy.original_mint,
+            derivative_mint: legacy.derivative_mint,
+            paused: 0,
+            lock_fee_override: None,
+            unlock_fee_override: None,
+            sol_fee: None,
+            total_locked: vault_balance,
+            total_unlocked: 0,
+            cumulative_fees: 0,
+            lock_count: 0,
+            unlock_count: 0,
+            created_at: migrated_at,
+            reserved: [0; RESERVED_ACCOUNT_BYTES],
+        }
+    }
+
+    // `locked` is what stays in the vault after the fee has left it
+    pub fn record_lock(&mut self, locked: u64, fee: u64) -> Result<()> {
+        self.total_locked = self
+            .total_locked
+            .checked_add(locked)
+            .ok_or(TwosideErrorCodes::Overflow)?;
+        self.cumulative_fees = self
+            .cumulative_fees
+            .checked_add(fee)
+            .ok_or(TwosideErrorCodes::Overflow)?;
+        self.lock_count = self
+            .lock_count
+            .checked_add(1)
+            .ok_or(TwosideErrorCodes::Overflow)?;
+        Ok(())
+    }
+
+    // `unlocked` is everything that left the vault, fee included
+    pub fn record_unlock(&mut self, unlocked: u64, fee: u64) -> Result<()> {
+        self.total_unlocked = self
+            .total_unlocked
+            .checked_add(unlocked)
+            .ok_or(TwosideErrorCodes::Overflow)?;
+        self.cumulative_fees = self
+            .cumulative_fees
+            .checked_add(fee)
+            .ok_or(TwosideErrorCodes::Overflow)?;
+        self.unlock_count = self
+            .unlock_count
+            .checked_add(1)
+            .ok_or(TwosideErrorCodes::Overflow)?;
+        Ok(())
+    }
+}
+
+// TokenInfo before layouts were versioned, only read by migrate_token_info
+#[derive(AnchorDeserialize)]
+pub struct TokenInfoV1 {
+    pub is_initialized: bool,    // 1
+    pub original_mint: Pubkey,   // 32
+    pub derivative_mint: Pubkey, // 32
+}
+
+impl TokenInfoV1 {
+    pub const LEN: usize = 1 + 32 + 32;
+}
+
+// Per-token alternative to taking the fee out of the locked token, charged
+// to the signer in SOL so the full amount is minted or released
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SolFee {
+    // fixed charge per lock or unlock
+    Flat {
+        lamports: u64,
+    },
+    // the regular token fee valued at the Pyth USD prices of the token and of
+    // SOL, refused once either is older than `max_price_age` seconds
+    Priced {
+        token_price_feed: Pubkey,
+        sol_price_feed: Pubkey,
+        max_price_age: u32,
+    },
+}
+
+impl SolFee {
+    pub const LEN: usize = 1 + 32 + 32 + 4;
+}
+
+// SolFee with the prices read for the current instruction
+#[derive(Clone, Copy, Debug)]
+pub enum SolFeeCharge {
+    Flat(u64),
+    Priced {
+        token_price: PriceFeedMessage,
+        sol_price: PriceFeedMessage,
+    },
+}
+
+// Pyth receiver accounts, mirrored to read a price without pulling in the SDK
+#[derive(AnchorDeserialize)]
+pub struct PriceUpdateV2 {
+    pub write_authority: Pubkey,
+    pub verification_level: VerificationLevel,
+    pub price_message: PriceFeedMessage,
+    pub posted_slot: u64,
+}
+
+#[derive(AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VerificationLevel {
+    Partial { num_signatures: u8 },
+    Full,
+}
+
+#[derive(AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PriceFeedMessage {
+    pub feed_id: [u8; 32],
+    pub price: i64,
+    pub conf: u64,
+    pub exponent: i32,
+    pub publish_time: i64,
+    pub prev_publish_time: i64,
+    pub ema_price: i64,
+    pub ema_conf: u64,
+}
+
+// Fee parameters fed to calculate_fee, min and max are in base units of the
+// token so they are only meaningful per token
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FeeConfig {
+    pub fee_bps: u16, // 16 / 8 = 2
+    pub min_fee: u64, // 64 / 8 = 8
+    pub max_fee: u64, // 64 / 8 = 8
+}
+
+impl FeeConfig {
+    pub const LEN: usize = 2 + 8 + 8;
+
+    pub const DEFAULT: FeeConfig = FeeConfig {
+        fee_bps: 50,
+        min_fee: 0,
+        max_fee: u64::MAX,
+    };
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FeeRecipient {
+    pub wallet: Pubkey,  // 32
+    pub weight_bps: u16, // 16 / 8 = 2
+}
+
+impl FeeRecipient {
+    pub const LEN: usize = 32 + 2;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FeeTier {
+    pub threshold: u64, // 64 / 8 = 8
+    pub fee_bps: u16,   // 16 / 8 = 2
+}
+
+impl FeeTier {
+    pub const LEN: usize = 8 + 2;
+}
+
+// What a tier threshold is compared against
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FeeTierBasis {
+    // size of the lock or unlock itself
+    Amount,
+    // signer's cumulative lock and unlock volume, including this one
+    Volume,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FeeLeg {
+    Lock,
+    Unlock,
+}
+
+#[account]
+pub struct TokenLabel {
+    pub original_mint: Pubkey, // 32
+    pub name: String,          // 4 + 32
+    pub symbol: String,        // 4 + 10
+    pub uri: String,           // 4 + 200
+}
+
+impl TokenLabel {
+    pub const LEN: usize =
+        32 + 4 + MAX_LABEL_NAME_LENGTH + 4 + MAX_LABEL_SYMBOL_LENGTH + 4 + MAX_LABEL_URI_LENGTH;
+}
 
-    #[account(mut)]
-    pub signer: Signer<'info>,
-    #[account(
-        mut,
-        token::mint = token_mint,
-        token::authority = signer,
-    )]
-    pub signer_token_ata: Account<'info, TokenAccount>,
-    #[account(
-        mut,
-        associated_token::mint = derivative_mint,
-        associated_token::authority = signer,
-    )]
-    pub signer_derivative_ata: Account<'info, TokenAccount>,
+#[account]
+pub struct TokenListEntry {
+    pub original_mint: Pubkey,   // 32
+    pub status: TokenListStatus, // 1
+}
 
-    #[account(
-        mut,
-        seeds = [
-            TOKEN_INFO_STATIC_SEED,
-            token_mint.key().as_ref()
-        ],
-        bump,
-        constraint = token_info.original_mint == token_mint.key() &&
-        token_info.is_initialized
-    )]
-    pub token_info: Box<Account<'info, TokenInfo>>,
+impl TokenListEntry {
+    pub const LEN: usize = 32 + 1;
+}
 
-    #[account(
-        seeds = [
-            VAULT_AUTHORITY_STATIC_SEED,
-            token_mint.key().as_ref()
-        ],
-        bump,
-    )]
-    /// CHECK: Token Vault's Authority.
-    pub vault_authority: UncheckedAccount<'info>,
-    #[account(
-        mut,
-        associated_token::mint = token_mint,
-        associated_token::authority = vault_authority,
-    )]
-    pub vault_ata: Account<'info, TokenAccount>,
+// keyed by a wallet or by the program ID of a caller reaching lock/unlock by CPI
+#[account]
+pub struct FeeExemption {
+    pub subject: Pubkey, // 32
+    pub exempt: bool,    // 1
+}
 
-    #[account(
-        mut,
-        seeds = [GLOBAL_INFO_STATIC_SEED],
-        bump,
-        constraint = global_info.is_initialized
-        @ ProgramError::UninitializedAccount
-    )]
-    pub global_info: Box<Account<'info, GlobalInfo>>,
+impl FeeExemption {
+    pub const LEN: usize = 32 + 1;
+}
 
-    #[account(
-        mut,
-        token::mint = token_mint,
-        constraint = founder_ata.owner == global_info.founder_wallet
-    )]
-    pub founder_ata: Account<'info, TokenAccount>,
-    #[account(
-        mut,
-        token::mint = token_mint,
-        constraint = developer_ata.owner == global_info.developer_wallet
-    )]
-    pub developer_ata: Account<'info, TokenAccount>,
+#[account]
+pub struct FeeTierSchedule {
+    pub original_mint: Pubkey, // 32
+    pub basis: FeeTierBasis,   // 1
+    pub tiers: Vec<FeeTier>,   // 4 + 10 * MAX_FEE_TIERS
 }
 
-pub const GLOBAL_INFO_STATIC_SEED: &[u8] = b"global_info";
-pub const TOKEN_INFO_STATIC_SEED: &[u8] = b"token_info";
-pub const VAULT_AUTHORITY_STATIC_SEED: &[u8] = b"vault_authority";
-pub const METADATA_STATIC_SEED: &[u8] = b"metadata";
-pub const DERIVATIVE_AUTHORITY_SEED: &[u8] = b"derivative_authority";
-pub const DERIVATIVE_MINT_STATIC_SEED: &[u8] = b"derivative_mint";
+impl FeeTierSchedule {
+    pub const LEN: usize = 32 + 1 + 4 + FeeTier::LEN * MAX_FEE_TIERS;
+}
 
 #[account]
-pub struct GlobalInfo {
-    pub is_initialized: bool,         // 1
-    pub developer_wallet: Pubkey,     // 32
-    pub founder_wallet: Pubkey,       // 32
-    pub fee_percentage: u8,           // 8 / 8 = 1
-    pub fee_percentage_divider: u16,  // 16 / 8 = 2
-    pub min_fee_for_distribution: u8, // 8 / 8 = 1
-    pub min_fee: u8,                  // 8 / 8 = 1
-    pub developer_fee_share: u8,      // 8 / 8 = 1
-    pub founder_fee_share: u8,        // 8 / 8 = 1
+pub struct UserVolume {
+    pub owner: Pubkey,         // 32
+    pub original_mint: Pubkey, // 32
+    pub volume: u64,           // 64 / 8 = 8
 }
 
-impl GlobalInfo {
-    pub const LEN: usize = 1 + 32 + 32 + 1 + 2 + 1 + 1 + 1 + 1;
+impl UserVolume {
+    pub const LEN: usize = 32 + 32 + 8;
 }
 
 #[account]
-pub struct TokenInfo {
-    pub is_initialized: bool,    // 1
-    pub original_mint: Pubkey,   // 32
-    pub derivative_mint: Pubkey, // 32
+pub struct ReferrerStats {
+    pub referrer: Pubkey,      // 32
+    pub original_mint: Pubkey, // 32
+    pub referral_count: u64,   // 64 / 8 = 8
+    pub referred_volume: u64,  // 64 / 8 = 8
+    pub fees_earned: u64,      // 64 / 8 = 8
+    pub lamports_earned: u64,  // 64 / 8 = 8
 }
 
-impl TokenInfo {
-    pub const LEN: usize = 1 + 32 + 32;
+impl ReferrerStats {
+    pub const LEN: usize = 32 + 32 + 8 + 8 + 8 + 8;
+}
+
+#[account]
+pub struct ReferrerEntry {
+    pub referrer: Pubkey, // 32
+    pub allowed: bool,    // 1
+}
+
+impl ReferrerEntry {
+    pub const LEN: usize = 32 + 1;
+}
+
+#[account]
+pub struct FeeVault {
+    pub original_mint: Pubkey,     // 32
+    pub balances: Vec<FeeBalance>, // 4 + 48 * MAX_FEE_BALANCES
+}
+
+impl FeeVault {
+    pub const LEN: usize = 32 + 4 + FeeBalance::LEN * MAX_FEE_BALANCES;
+
+    pub fn credit(&mut self, wallet: Pubkey, amount: u64, lamports: u64) -> Result<()> {
+        if amount == 0 && lamports == 0 {
+            return Ok(());
+        }
+        if let Some(balance) = self.balances.iter_mut().find(|b| b.wallet == wallet) {
+            balance.amount = balance
+                .amount
+                .checked_add(amount)
+                .ok_or(TwosideErrorCodes::Overflow)?;
+            balance.lamports = balance
+                .lamports
+                .checked_add(lamports)
+                .ok_or(TwosideErrorCodes::Overflow)?;
+            return Ok(());
+        }
+        require!(
+            self.balances.len() < MAX_FEE_BALANCES,
+            TwosideErrorCodes::FeeVaultFull
+        );
+        self.balances.push(FeeBalance {
+            wallet,
+            amount,
+            lamports,
+        });
+        Ok(())
+    }
+
+    // claimed entries are dropped so the slot can go to another recipient
+    pub fn take(&mut self, wallet: Pubkey) -> FeeBalance {
+        match self.balances.iter().position(|b| b.wallet == wallet) {
+            Some(index) => self.balances.swap_remove(index),
+            None => FeeBalance {
+                wallet,
+                amount: 0,
+                lamports: 0,
+            },
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FeeBalance {
+    pub wallet: Pubkey, // 32
+    pub amount: u64,    // 64 / 8 = 8
+    pub lamports: u64,  // 64 / 8 = 8
+}
+
+impl FeeBalance {
+    pub const LEN: usize = 32 + 8 + 8;
 }
 
 // Error Codes
@@ -752,25 +3648,124 @@ pub enum TwosideErrorCodes {
     AmountInsufficientAfterFee,
     #[msg("Overflow")]
     Overflow,
+    #[msg("Signer is not the program admin.")]
+    Unauthorized,
+    #[msg("Minimum fee cannot exceed the maximum fee.")]
+    InvalidFeeBounds,
+    #[msg("Fee recipient weights must sum to 10,000 basis points.")]
+    InvalidFeeShares,
+    #[msg("Fee exceeds the maximum allowed.")]
+    FeeTooHigh,
+    #[msg("No admin transfer is pending.")]
+    NoPendingAdmin,
+    #[msg("Operation is paused.")]
+    Paused,
+    #[msg("Unknown pause flags.")]
+    InvalidPauseFlags,
+    #[msg("Program data account does not belong to this program.")]
+    InvalidProgramData,
+    #[msg("Mint has an extension that breaks 1:1 backing.")]
+    UnsupportedMintExtension,
+    #[msg("Derivative mint is not owned by the derivative token program.")]
+    InvalidDerivativeTokenProgram,
+    #[msg("Metaplex program and derivative metadata accounts are required.")]
+    MissingMetaplexAccounts,
+    #[msg("Token label name, symbol or uri is empty or too long.")]
+    InvalidTokenLabel,
+    #[msg("Token is not allowed by the token list.")]
+    TokenNotAllowed,
+    #[msg("Fee shares do not add up to the fee.")]
+    FeeDistributionMismatch,
+    #[msg("Between one and five fee recipients with a valid remainder index are required.")]
+    InvalidFeeRecipients,
+    #[msg("Fee vault is tracking too many unclaimed recipients.")]
+    FeeVaultFull,
+    #[msg("No fees accrued for this recipient.")]
+    NoFeesToClaim,
+    #[msg("Fee exemption account does not belong to the calling program.")]
+    InvalidFeeExemptionAccount,
+    #[msg("Referral share cannot exceed the whole fee.")]
+    InvalidReferralFee,
+    #[msg("Referrer, referrer token account, stats and entry go together.")]
+    InvalidReferrerAccounts,
+    #[msg("Signer cannot refer itself.")]
+    SelfReferral,
+    #[msg("Fee tiers must be at most four, with rising thresholds and non-increasing fees.")]
+    InvalidFeeTiers,
+    #[msg("Vault received a different amount than the transfer should deliver.")]
+    TransferAmountMismatch,
+    #[msg("Account already uses the current layout.")]
+    AccountAlreadyMigrated,
+    #[msg("Account must be migrated to the current layout first.")]
+    AccountNotMigrated,
+    #[msg("Vault balance no longer covers the derivative supply.")]
+    SolvencyViolation,
+    #[msg("Fee tier rate exceeds the base fee rate it discounts.")]
+    FeeTierAboveBaseRate,
+    #[msg("SOL fee exceeds the maximum.")]
+    SolFeeTooHigh,
+    #[msg("Price age must be between 1 second and the maximum.")]
+    InvalidPriceAge,
+    #[msg("Price feed is missing or not the configured Pyth account.")]
+    InvalidPriceFeed,
+    #[msg("Oracle price is older than the allowed age.")]
+    StalePrice,
+    #[msg("Oracle price must be positive with a narrow confidence interval.")]
+    InvalidOraclePrice,
+    #[msg("Referrer is not on the admin's referrer list.")]
+    ReferrerNotAllowed,
 }
 
 // Events
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct FeeShare {
+    pub wallet: Pubkey,
+    pub amount: u64,
+}
+
 #[event]
-pub struct DeveloperFeeShareDistributed {
-    pub developer_wallet: Pubkey,
+pub struct FeeAccrued {
     pub token: Pubkey,
-    pub amount: u64,
+    pub leg: FeeLeg,
+    pub fee: u64,
+    pub shares: Vec<FeeShare>,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ReferralFeePaid {
+    pub referrer: Pubkey,
+    pub token: Pubkey,
+    pub leg: FeeLeg,
+    pub volume: u64,
+    pub fee: u64,
+    pub referral_fee: u64,
+    pub fee_lamports: u64,
+    pub referral_lamports: u64,
     pub timestamp: i64,
 }
 
 #[event]
-pub struct FounderFeeShareDistributed {
-    pub founder_wallet: Pubkey,
+pub struct FeesClaimed {
+    pub claimer: Pubkey,
+    pub recipient: Pubkey,
     pub token: Pubkey,
     pub amount: u64,
+    pub lamports: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SolFeeAccrued {
+    pub token: Pubkey,
+    pub leg: FeeLeg,
+    pub fee_lamports: u64,
+    pub shares: Vec<FeeShare>,
     pub timestamp: i64,
 }
 
+// AssetsLocked and AssetsUnlocked keep their layout for existing indexers, the
+// V2 events are emitted next to them with the full picture of the operation
 #[event]
 pub struct AssetsLocked {
     pub account: Pubkey,
@@ -787,9 +3782,383 @@ pub struct AssetsUnlocked {
     pub timestamp: i64,
 }
 
+#[event]
+pub struct AssetsLockedV2 {
+    pub account: Pubkey,
+    pub token: Pubkey,
+    pub derivative_mint: Pubkey,
+    pub source_ata: Pubkey,
+    pub destination_ata: Pubkey,
+    // what reached the vault, after any transfer fee of the mint
+    pub amount: u64,
+    pub fee: u64,
+    pub fee_lamports: u64,
+    // derivatives minted
+    pub net_amount: u64,
+    pub vault_balance: u64,
+    pub derivative_supply: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AssetsUnlockedV2 {
+    pub account: Pubkey,
+    pub token: Pubkey,
+    pub derivative_mint: Pubkey,
+    pub source_ata: Pubkey,
+    pub destination_ata: Pubkey,
+    // derivatives burned
+    pub amount: u64,
+    pub fee: u64,
+    pub fee_lamports: u64,
+    // tokens sent out of the vault, before any transfer fee of the mint
+    pub net_amount: u64,
+    pub vault_balance: u64,
+    pub derivative_supply: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
 #[event]
 pub struct DerivativeTokenMinted {
     pub token: Pubkey,
     pub derivative: Pubkey,
     pub timestamp: i64,
 }
+
+#[event]
+pub struct GlobalConfigUpdated {
+    pub admin: Pubkey,
+    pub old_config: GlobalConfig,
+    pub new_config: GlobalConfig,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AdminTransferProposed {
+    pub admin: Pubkey,
+    pub pending_admin: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AdminTransferAccepted {
+    pub old_admin: Pubkey,
+    pub new_admin: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AdminTransferCancelled {
+    pub admin: Pubkey,
+    pub cancelled_admin: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct GlobalPauseUpdated {
+    pub admin: Pubkey,
+    pub paused: u8,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TokenPauseUpdated {
+    pub admin: Pubkey,
+    pub token: Pubkey,
+    pub paused: u8,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TokenLabelSet {
+    pub admin: Pubkey,
+    pub token: Pubkey,
+    pub name: String,
+    pub symbol: String,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TokenListStatusUpdated {
+    pub admin: Pubkey,
+    pub token: Pubkey,
+    pub status: TokenListStatus,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TokenFeeOverrideUpdated {
+    pub admin: Pubkey,
+    pub token: Pubkey,
+    pub lock_fee_override: Option<FeeConfig>,
+    pub unlock_fee_override: Option<FeeConfig>,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FeeExemptionUpdated {
+    pub admin: Pubkey,
+    pub subject: Pubkey,
+    pub exempt: bool,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ReferrerStatusUpdated {
+    pub admin: Pubkey,
+    pub referrer: Pubkey,
+    pub allowed: bool,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FeeTiersUpdated {
+    pub admin: Pubkey,
+    pub token: Pubkey,
+    pub basis: FeeTierBasis,
+    pub tiers: Vec<FeeTier>,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TokenSolFeeUpdated {
+    pub admin: Pubkey,
+    pub token: Pubkey,
+    pub sol_fee: Option<SolFee>,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TokenInfoMigrated {
+    pub signer: Pubkey,
+    pub token: Pubkey,
+    pub version: u8,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct GlobalInfoMigrated {
+    pub admin: Pubkey,
+    pub version: u8,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SolvencyReport {
+    pub token: Pubkey,
+    pub vault_balance: u64,
+    pub derivative_supply: u64,
+    // negative when the vault falls short of the supply
+    pub surplus: i128,
+    pub timestamp: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CASES: usize = 20_000;
+
+    // xorshift64 with a fixed seed, so a failing case reproduces
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, bound: u64) -> u64 {
+            self.next() % bound
+        }
+    }
+
+    // small, mid-sized and full-range amounts, so min and max clamps both hit
+    fn random_amount(rng: &mut Rng) -> u64 {
+        match rng.below(3) {
+            0 => rng.below(10_000),
+            1 => rng.below(1 << 40),
+            _ => rng.next(),
+        }
+    }
+
+    fn random_fee_config(rng: &mut Rng) -> FeeConfig {
+        let min_fee = if rng.below(2) == 0 {
+            0
+        } else {
+            rng.below(1_000)
+        };
+        let max_fee = if rng.below(2) == 0 {
+            u64::MAX
+        } else {
+            min_fee + rng.below(1 << 30)
+        };
+        let fee_config = FeeConfig {
+            fee_bps: rng.below(MAX_FEE_BASIS_POINTS + 1) as u16,
+            min_fee,
+            max_fee,
+        };
+        validate_fee_config(&fee_config).unwrap();
+        fee_config
+    }
+
+    fn random_fee_tiers(rng: &mut Rng, base_fee_bps: u16) -> Vec<FeeTier> {
+        let mut threshold = 0;
+        let mut fee_bps = base_fee_bps;
+        let mut fee_tiers = Vec::new();
+        for _ in 0..rng.below(MAX_FEE_TIERS as u64 + 1) {
+            threshold += 1 + rng.below(1 << 32);
+            fee_bps -= rng.below(fee_bps as u64 + 1) as u16;
+            fee_tiers.push(FeeTier { threshold, fee_bps });
+        }
+        validate_fee_tiers(&fee_tiers, base_fee_bps).unwrap();
+        fee_tiers
+    }
+
+    fn random_global_info(rng: &mut Rng) -> GlobalInfo {
+        let count = 1 + rng.below(MAX_FEE_RECIPIENTS as u64) as usize;
+        let mut remaining = BASIS_POINTS_DIVIDER;
+        let fee_recipients = (0..count)
+            .map(|index| {
+                let weight_bps = if index + 1 == count {
+                    remaining
+                } else {
+                    rng.below(remaining + 1)
+                };
+                remaining -= weight_bps;
+                FeeRecipient {
+                    wallet: Pubkey::new_unique(),
+                    weight_bps: weight_bps as u16,
+                }
+            })
+            .collect();
+        GlobalInfo {
+            version: GLOBAL_INFO_VERSION,
+            is_initialized: true,
+            fee_recipients,
+            lock_fee: FeeConfig::DEFAULT,
+            unlock_fee: FeeConfig::DEFAULT,
+            admin: Pubkey::new_unique(),
+            pending_admin: Pubkey::default(),
+            paused: 0,
+            derivative_metadata_mode: DerivativeMetadataMode::Metaplex,
+            permissionless_registration: true,
+            token_list_mode: TokenListMode::Open,
+            fee_remainder_index: rng.below(count as u64) as u8,
+            referral_fee_bps: 0,
+            reserved: [0; RESERVED_ACCOUNT_BYTES],
+        }
+    }
+
+    fn random_price(rng: &mut Rng, low: u64, high: u64) -> PriceFeedMessage {
+        PriceFeedMessage {
+            feed_id: [0; 32],
+            price: (low + rng.below(high - low)) as i64,
+            conf: 0,
+            exponent: -8,
+            publish_time: 0,
+            prev_publish_time: 0,
+            ema_price: 0,
+            ema_conf: 0,
+        }
+    }
+
+    #[test]
+    fn calculate_fee_leaves_something_to_lock() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..CASES {
+            let amount = random_amount(&mut rng);
+            let fee_config = random_fee_config(&mut rng);
+            let fee_tiers = random_fee_tiers(&mut rng, fee_config.fee_bps);
+            let tier_metric = random_amount(&mut rng);
+            match calculate_fee(amount, &fee_config, &fee_tiers, tier_metric) {
+                Ok(fee) => {
+                    assert!(fee < amount, "fee {fee} of {amount} under {fee_config:?}");
+                    assert!(fee >= fee_config.min_fee && fee <= fee_config.max_fee);
+                }
+                Err(err) => assert_eq!(
+                    err,
+                    TwosideErrorCodes::AmountInsufficientAfterFee.into(),
+                    "amount {amount} under {fee_config:?}"
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn split_fee_shares_add_up_to_fee() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..CASES {
+            let global_info = random_global_info(&mut rng);
+            let fee = random_amount(&mut rng);
+            let shares = split_fee(fee, &global_info).unwrap();
+            assert_eq!(shares.len(), global_info.fee_recipients.len());
+            assert_eq!(
+                shares.iter().sum::<u64>(),
+                fee,
+                "{:?}",
+                global_info.fee_recipients
+            );
+        }
+    }
+
+    #[test]
+    fn calculate_fee_charge_is_bounded() {
+        let mut rng = Rng(0xd1b5_4a32_d192_ed03);
+        for _ in 0..CASES {
+            let amount = random_amount(&mut rng);
+            let fee_config = random_fee_config(&mut rng);
+            let fee_tiers = FeeTierSchedule {
+                original_mint: Pubkey::default(),
+                basis: if rng.below(2) == 0 {
+                    FeeTierBasis::Amount
+                } else {
+                    FeeTierBasis::Volume
+                },
+                tiers: random_fee_tiers(&mut rng, fee_config.fee_bps),
+            };
+            let volume = amount.saturating_add(random_amount(&mut rng));
+            let sol_fee = match rng.below(3) {
+                0 => None,
+                1 => Some(SolFeeCharge::Flat(rng.below(MAX_SOL_FEE_LAMPORTS + 1))),
+                _ => Some(SolFeeCharge::Priced {
+                    token_price: random_price(&mut rng, 1, 1 << 40),
+                    sol_price: random_price(&mut rng, 1 << 30, 1 << 40),
+                }),
+            };
+            let decimals = rng.below(10) as u8;
+            let global_info = random_global_info(&mut rng);
+
+            let (fee, fee_lamports) = match calculate_fee_charge(
+                amount,
+                &fee_config,
+                &fee_tiers,
+                volume,
+                sol_fee,
+                decimals,
+            ) {
+                Ok(charge) => charge,
+                Err(err) => {
+                    assert!(
+                        err == TwosideErrorCodes::AmountInsufficientAfterFee.into()
+                            || err == TwosideErrorCodes::SolFeeTooHigh.into()
+                            || err == TwosideErrorCodes::Overflow.into(),
+                        "{err:?}"
+                    );
+                    continue;
+                }
+            };
+            assert!(fee == 0 || fee_lamports == 0);
+            assert!(fee <= amount, "fee {fee} of {amount} under {fee_config:?}");
+            assert!(fee_lamports <= MAX_SOL_FEE_LAMPORTS);
+            for charge in [fee, fee_lamports] {
+                let shares = split_fee(charge, &global_info).unwrap();
+                assert_eq!(shares.iter().sum::<u64>(), charge);
+            }
+        }
+    }
+}
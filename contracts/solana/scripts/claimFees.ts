@@ -0,0 +1,26 @@
+import * as splToken from "@solana/spl-token";
+import { tokenMint, user, program, developer, founder } from "./setup";
+
+(async function main() {
+  try {
+    console.log("Claiming Fees :-");
+    for (const recipient of [developer.publicKey, founder]) {
+      const sig = await program.methods
+        .claimFees()
+        .accounts({
+          tokenProgram: splToken.TOKEN_PROGRAM_ID,
+          tokenMint: tokenMint,
+          signer: user.publicKey,
+          recipient: recipient,
+        })
+        .signers([user])
+        .rpc();
+      console.log("Recipient: ", recipient.toString());
+      console.log("Sig: ", sig);
+    }
+    console.log("");
+  } catch (e: any) {
+    console.error("Fatal error:", e);
+    process.exit(1);
+  }
+})();
@@ -1,13 +1,20 @@
-import { developer, founder, program, user } from "./setup";
+import * as anchor from "@coral-xyz/anchor";
+import { developer, founder, program, programId, user } from "./setup";
 
 (async function main() {
   try {
     console.log("Initializing Program :-");
     console.log("");
+    // user must be the program's upgrade authority
+    const [programData] = anchor.web3.PublicKey.findProgramAddressSync(
+      [programId.toBuffer()],
+      new anchor.web3.PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+    );
     const sig = await program.methods
       .initializeProgram(developer.publicKey, founder)
       .accounts({
         signer: user.publicKey,
+        programData: programData,
       })
       .signers([user])
       .rpc();
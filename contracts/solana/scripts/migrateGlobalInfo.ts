@@ -0,0 +1,26 @@
+import * as anchor from "@coral-xyz/anchor";
+import { program, programId, user } from "./setup";
+
+(async function main() {
+  try {
+    console.log("Migrating Global Info :-");
+    // user must be the program's upgrade authority, it becomes the admin
+    const [programData] = anchor.web3.PublicKey.findProgramAddressSync(
+      [programId.toBuffer()],
+      new anchor.web3.PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+    );
+    const sig = await program.methods
+      .migrateGlobalInfo()
+      .accounts({
+        signer: user.publicKey,
+        programData: programData,
+      })
+      .signers([user])
+      .rpc();
+    console.log("Sig: ", sig);
+    console.log("");
+  } catch (e: any) {
+    console.error("Fatal error:", e);
+    process.exit(1);
+  }
+})();
@@ -0,0 +1,22 @@
+import * as splToken from "@solana/spl-token";
+import { tokenMint, user, program } from "./setup";
+
+(async function main() {
+  try {
+    console.log("Migrating Token Info :-");
+    const sig = await program.methods
+      .migrateTokenInfo()
+      .accounts({
+        tokenProgram: splToken.TOKEN_PROGRAM_ID,
+        signer: user.publicKey,
+        tokenMint: tokenMint,
+      })
+      .signers([user])
+      .rpc();
+    console.log("Sig: ", sig);
+    console.log("");
+  } catch (e: any) {
+    console.error("Fatal error:", e);
+    process.exit(1);
+  }
+})();
@@ -0,0 +1,53 @@
+import * as anchor from "@coral-xyz/anchor";
+import { MPL_TOKEN_METADATA_PROGRAM_ID } from "@metaplex-foundation/mpl-token-metadata";
+import * as splToken from "@solana/spl-token";
+import { tokenMint, user, program, programId } from "./setup";
+import { PublicKey } from "@solana/web3.js";
+
+(async function main() {
+  try {
+    const [tokenMetadataPDA, tokenMetadataBump] =
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("metadata"),
+          new PublicKey(MPL_TOKEN_METADATA_PROGRAM_ID).toBuffer(),
+          tokenMint.toBuffer(),
+        ],
+        new PublicKey(MPL_TOKEN_METADATA_PROGRAM_ID)
+      );
+
+    const [derivativeMintPDA] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("derivative_mint"), tokenMint.toBuffer()],
+      programId
+    );
+    const [derivativeMetadataPDA] =
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("metadata"),
+          new PublicKey(MPL_TOKEN_METADATA_PROGRAM_ID).toBuffer(),
+          derivativeMintPDA.toBuffer(),
+        ],
+        new PublicKey(MPL_TOKEN_METADATA_PROGRAM_ID)
+      );
+
+    console.log("Registering Token :-");
+    const sig = await program.methods
+      .registerToken()
+      .accounts({
+        tokenProgram: splToken.TOKEN_PROGRAM_ID,
+        derivativeTokenProgram: splToken.TOKEN_PROGRAM_ID,
+        tokenMint: tokenMint,
+        tokenMetadata: tokenMetadataPDA,
+        derivativeMetadata: derivativeMetadataPDA,
+        signer: user.publicKey,
+        mplTokenMetadataProgram: MPL_TOKEN_METADATA_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+    console.log("Sig: ", sig);
+    console.log("");
+  } catch (e: any) {
+    console.error("Fatal error:", e);
+    process.exit(1);
+  }
+})();
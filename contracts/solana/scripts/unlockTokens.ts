@@ -8,22 +8,11 @@ import {
   user,
   program,
   userAta,
-  developer,
-  founder,
 } from "./setup";
 import { PublicKey } from "@solana/web3.js";
 
 (async function main() {
   try {
-    const developerAta = splToken.getAssociatedTokenAddressSync(
-      tokenMint,
-      developer.publicKey
-    );
-    const founderAta = splToken.getAssociatedTokenAddressSync(
-      tokenMint,
-      founder
-    );
-
     const [tokenMetadataPDA, tokenMetadataBump] =
       anchor.web3.PublicKey.findProgramAddressSync(
         [
@@ -39,11 +28,19 @@ import { PublicKey } from "@solana/web3.js";
     const sig = await program.methods
       .unlock(new anchor.BN(unlockAmount))
       .accounts({
+        tokenProgram: splToken.TOKEN_PROGRAM_ID,
+        derivativeTokenProgram: splToken.TOKEN_PROGRAM_ID,
         tokenMint: tokenMint,
         signer: user.publicKey,
         signerTokenAta: userAta,
-        developerAta: developerAta,
-        founderAta: founderAta,
+        callerFeeExemption: null,
+        tokenPriceFeed: null,
+        solPriceFeed: null,
+        referrer: null,
+        referrerTokenAccount: null,
+        referrerStats: null,
+        referrerEntry: null,
+        userVolume: null,
       })
       .signers([user])
       .rpc();
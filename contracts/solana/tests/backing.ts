@@ -0,0 +1,310 @@
+import { assert } from "chai";
+import * as anchor from "@coral-xyz/anchor";
+import * as splToken from "@solana/spl-token";
+import { setup, U64_MAX } from "./setup";
+
+describe("Vault Backing", () => {
+  const decimals = 6;
+  const initialBalance = 10_000 * 10 ** decimals;
+  // a fixed odd fee leaves one unit of dust on every 50/50 split
+  const oddFee = {
+    feeBps: 0,
+    minFee: new anchor.BN(3),
+    maxFee: new anchor.BN(3),
+  };
+
+  async function balances(tokenMint: anchor.web3.PublicKey) {
+    const { pda: derivativeMint } = setup.getDerivativeMint(tokenMint);
+    const { ata: vaultAta } = setup.getTokenVault(tokenMint);
+    const { ata: feeVaultAta } = setup.getFeeVault(tokenMint);
+    const tokenInfo = await setup.program.account.tokenInfo.fetch(
+      setup.getTokenInfoPDA(tokenMint).pda
+    );
+    return {
+      tokenInfo: tokenInfo,
+      vault: (await splToken.getAccount(setup.connection, vaultAta)).amount,
+      supply: (await splToken.getMint(setup.connection, derivativeMint))
+        .supply,
+      feeVault: (await splToken.getAccount(setup.connection, feeVaultAta))
+        .amount,
+      developer: await setup.feeBalance(tokenMint, setup.developer.publicKey),
+      founder: await setup.feeBalance(tokenMint, setup.founder.publicKey),
+    };
+  }
+
+  async function registerWithFee(
+    tokenMint: anchor.web3.PublicKey,
+    feeConfig: object
+  ) {
+    await setup.registerToken(tokenMint);
+    await setup.program.methods
+      .setTokenFeeOverride(feeConfig, feeConfig)
+      .accounts({
+        admin: setup.admin.publicKey,
+        tokenInfo: setup.getTokenInfoPDA(tokenMint).pda,
+      })
+      .signers([setup.admin])
+      .rpc();
+  }
+
+  async function lock(tokenMint: anchor.web3.PublicKey, amount: number) {
+    return await setup.program.methods
+      .lock(new anchor.BN(amount))
+      .accounts(await setup.tokenOpAccounts(tokenMint, setup.user.publicKey))
+      .signers([setup.user])
+      .rpc({ commitment: "confirmed" });
+  }
+
+  async function unlock(tokenMint: anchor.web3.PublicKey, amount: number) {
+    return await setup.program.methods
+      .unlock(new anchor.BN(amount))
+      .accounts(await setup.tokenOpAccounts(tokenMint, setup.user.publicKey))
+      .signers([setup.user])
+      .rpc({ commitment: "confirmed" });
+  }
+
+  async function setFeeRemainderIndex(index: number) {
+    await setup.program.methods
+      .updateGlobalConfig({
+        ...setup.defaultGlobalConfig(),
+        feeRemainderIndex: index,
+      })
+      .accounts({
+        admin: setup.admin.publicKey,
+      })
+      .signers([setup.admin])
+      .rpc();
+  }
+
+  it("Fee Remainder Goes To Configured Recipient", async () => {
+    const tokenMint = await setup.createTestToken(decimals, initialBalance);
+    await registerWithFee(tokenMint, oddFee);
+
+    let before = await balances(tokenMint);
+    await lock(tokenMint, 1_000);
+    let after = await balances(tokenMint);
+    assert(after.developer - before.developer == BigInt(1), "Wrong Dev Share");
+    assert(after.founder - before.founder == BigInt(2), "Wrong Founder Share");
+
+    await setFeeRemainderIndex(0);
+    try {
+      before = after;
+      await lock(tokenMint, 1_000);
+      after = await balances(tokenMint);
+      assert(
+        after.developer - before.developer == BigInt(2),
+        "Remainder Not Sent To Developer"
+      );
+      assert(
+        after.founder - before.founder == BigInt(1),
+        "Wrong Founder Share"
+      );
+    } finally {
+      await setFeeRemainderIndex(1);
+    }
+    assert(after.vault == after.supply, "Vault Does Not Match Supply");
+  });
+
+  const feeSchedules = [
+    {
+      name: "Odd Fixed Fee",
+      feeConfig: oddFee,
+      expectedFee: (_amount: number) => 3,
+    },
+    {
+      name: "Basis Point Fee",
+      feeConfig: { feeBps: 37, minFee: new anchor.BN(1), maxFee: U64_MAX },
+      expectedFee: (amount: number) =>
+        Math.max(1, Math.floor((amount * 37 + 5_000) / 10_000)),
+    },
+  ];
+
+  for (const { name, feeConfig, expectedFee } of feeSchedules) {
+    it(`Vault Matches Supply After Locks And Unlocks (${name})`, async () => {
+      const tokenMint = await setup.createTestToken(decimals, initialBalance);
+      await registerWithFee(tokenMint, feeConfig);
+      const { pda: derivativeMint } = setup.getDerivativeMint(tokenMint);
+
+      // deterministic Park-Miller sequence so failures are reproducible
+      let seed = 7;
+      const nextRandom = (bound: number) => {
+        seed = (seed * 16_807) % 2_147_483_647;
+        return seed % bound;
+      };
+
+      for (let step = 0; step < 12; step++) {
+        const before = await balances(tokenMint);
+        const derivativeBalance = (
+          await setup.getTokenATA(derivativeMint, setup.user.publicKey)
+        ).amount;
+
+        // odd amounts so splits never divide evenly
+        const amount = 2 * nextRandom(50_000) + 1_001;
+        const unlocking =
+          nextRandom(2) == 0 && derivativeBalance >= BigInt(amount);
+        if (unlocking) {
+          await unlock(tokenMint, amount);
+        } else {
+          await lock(tokenMint, amount);
+        }
+
+        const after = await balances(tokenMint);
+        const accrued =
+          after.developer - before.developer + after.founder - before.founder;
+        const vaultDelta = after.vault - before.vault;
+        const expectedVaultDelta = unlocking
+          ? -BigInt(amount)
+          : BigInt(amount) - accrued;
+
+        assert(
+          after.vault == after.supply,
+          `Vault ${after.vault} != Supply ${after.supply} At Step ${step}`
+        );
+        assert(
+          accrued == BigInt(expectedFee(amount)),
+          `Fee Balances Do Not Add Up At Step ${step}`
+        );
+        assert(
+          after.feeVault == after.developer + after.founder,
+          `Fee Vault Does Not Match Balances At Step ${step}`
+        );
+        assert(
+          vaultDelta == expectedVaultDelta,
+          `Wrong Vault Movement At Step ${step}`
+        );
+
+        // the on-chain books agree with the token accounts
+        const { tokenInfo } = after;
+        const booked = tokenInfo.totalLocked.sub(tokenInfo.totalUnlocked);
+        assert(
+          BigInt(booked.toString()) == after.vault,
+          `Token Info Totals Do Not Match Vault At Step ${step}`
+        );
+        assert(
+          BigInt(tokenInfo.cumulativeFees.toString()) == after.feeVault,
+          `Token Info Fees Do Not Match Fee Vault At Step ${step}`
+        );
+        assert(
+          tokenInfo.lockCount.add(tokenInfo.unlockCount).eqn(step + 1),
+          `Wrong Operation Count At Step ${step}`
+        );
+      }
+    });
+  }
+
+  it("Solvency Report", async () => {
+    const tokenMint = await setup.createTestToken(decimals, initialBalance);
+    await registerWithFee(tokenMint, oddFee);
+    await lock(tokenMint, 1_000);
+
+    const report = async () => {
+      const signature = await setup.program.methods
+        .verifySolvency()
+        .accounts({
+          tokenProgram: splToken.TOKEN_PROGRAM_ID,
+          derivativeTokenProgram: splToken.TOKEN_PROGRAM_ID,
+          tokenMint: tokenMint,
+          derivativeMint: setup.getDerivativeMint(tokenMint).pda,
+        })
+        .rpc({ commitment: "confirmed" });
+      const events = await setup.getEvents(signature);
+      return events.find((event) => event.name == "solvencyReport").data;
+    };
+
+    let solvency = await report();
+    const { vault, supply } = await balances(tokenMint);
+    assert(
+      solvency.vaultBalance.eq(new anchor.BN(vault.toString())),
+      "Wrong Reported Vault Balance"
+    );
+    assert(
+      solvency.derivativeSupply.eq(new anchor.BN(supply.toString())),
+      "Wrong Reported Supply"
+    );
+    assert(solvency.surplus.eqn(0), "Fresh Vault Has Surplus");
+
+    // tokens sent straight to the vault back nothing and show as surplus
+    const { ata: vaultAta } = setup.getTokenVault(tokenMint);
+    await splToken.transfer(
+      setup.connection,
+      setup.user,
+      (await setup.getTokenATA(tokenMint, setup.user.publicKey)).address,
+      vaultAta,
+      setup.user,
+      500
+    );
+    solvency = await report();
+    assert(solvency.surplus.eqn(500), "Donation Not Reported As Surplus");
+
+    await unlock(tokenMint, 900);
+    solvency = await report();
+    assert(solvency.surplus.eqn(500), "Unlock Changed Surplus");
+  });
+
+  it("Lock And Unlock Events", async () => {
+    const tokenMint = await setup.createTestToken(decimals, initialBalance);
+    await registerWithFee(tokenMint, oddFee);
+    const { pda: derivativeMint } = setup.getDerivativeMint(tokenMint);
+    const userTokenAta = (
+      await setup.getTokenATA(tokenMint, setup.user.publicKey)
+    ).address;
+    const userDerivativeAta = splToken.getAssociatedTokenAddressSync(
+      derivativeMint,
+      setup.user.publicKey
+    );
+
+    const eventOf = async (signature: string, name: string) => {
+      const events = await setup.getEvents(signature);
+      return events.find((event) => event.name == name).data;
+    };
+    const assertTotals = async (event: any, step: string) => {
+      const { vault, supply } = await balances(tokenMint);
+      assert(
+        event.vaultBalance.eq(new anchor.BN(vault.toString())),
+        `Wrong Vault Balance After ${step}`
+      );
+      assert(
+        event.derivativeSupply.eq(new anchor.BN(supply.toString())),
+        `Wrong Derivative Supply After ${step}`
+      );
+      assert(event.slot.gtn(0), `Slot Missing After ${step}`);
+    };
+
+    let signature = await lock(tokenMint, 1_000);
+    const locked = await eventOf(signature, "assetsLockedV2");
+    assert(locked.derivativeMint.equals(derivativeMint), "Wrong Mint");
+    assert(locked.sourceAta.equals(userTokenAta), "Wrong Lock Source");
+    assert(
+      locked.destinationAta.equals(userDerivativeAta),
+      "Wrong Lock Destination"
+    );
+    assert(locked.amount.eqn(1_000), "Wrong Locked Amount");
+    assert(locked.fee.eqn(3), "Wrong Lock Fee");
+    assert(locked.netAmount.eqn(997), "Wrong Minted Amount");
+    await assertTotals(locked, "Lock");
+    // the original events are still emitted, unchanged, for existing indexers
+    const legacyLocked = await eventOf(signature, "assetsLocked");
+    assert(legacyLocked.amount.eqn(1_000), "Legacy Lock Event Missing");
+    assert.deepEqual(
+      Object.keys(legacyLocked),
+      ["account", "token", "amount", "timestamp"],
+      "Legacy Lock Event Changed"
+    );
+
+    signature = await unlock(tokenMint, 500);
+    const unlocked = await eventOf(signature, "assetsUnlockedV2");
+    assert(unlocked.sourceAta.equals(userDerivativeAta), "Wrong Source");
+    assert(unlocked.destinationAta.equals(userTokenAta), "Wrong Destination");
+    assert(unlocked.amount.eqn(500), "Wrong Burned Amount");
+    assert(unlocked.fee.eqn(3), "Wrong Unlock Fee");
+    assert(unlocked.netAmount.eqn(497), "Wrong Released Amount");
+    await assertTotals(unlocked, "Unlock");
+    const legacyUnlocked = await eventOf(signature, "assetsUnlocked");
+    assert(legacyUnlocked.amount.eqn(500), "Legacy Unlock Event Missing");
+    assert.deepEqual(
+      Object.keys(legacyUnlocked),
+      ["account", "token", "amount", "timestamp"],
+      "Legacy Unlock Event Changed"
+    );
+  });
+});
@@ -0,0 +1,777 @@
+import { assert } from "chai";
+import * as anchor from "@coral-xyz/anchor";
+import * as splToken from "@solana/spl-token";
+import { expectTwosideError, setup, U64_MAX } from "./setup";
+
+describe("Fees", () => {
+  const decimals = 6;
+  const lockAmount = 1_000 * 10 ** decimals;
+  const noFee = {
+    feeBps: 0,
+    minFee: new anchor.BN(0),
+    maxFee: U64_MAX,
+  };
+
+  async function setTokenFeeOverride(
+    tokenMint: anchor.web3.PublicKey,
+    lockFeeOverride: object | null,
+    unlockFeeOverride: object | null = null,
+    admin: anchor.web3.Keypair = setup.admin
+  ) {
+    return await setup.program.methods
+      .setTokenFeeOverride(lockFeeOverride, unlockFeeOverride)
+      .accounts({
+        admin: admin.publicKey,
+        tokenInfo: setup.getTokenInfoPDA(tokenMint).pda,
+      })
+      .signers([admin])
+      .rpc();
+  }
+
+  async function updateGlobalConfig(config: object) {
+    await setup.program.methods
+      .updateGlobalConfig({ ...setup.defaultGlobalConfig(), ...config })
+      .accounts({
+        admin: setup.admin.publicKey,
+      })
+      .signers([setup.admin])
+      .rpc();
+  }
+
+  async function derivativeSupply(tokenMint: anchor.web3.PublicKey) {
+    const { pda: derivativeMint } = setup.getDerivativeMint(tokenMint);
+    return (await splToken.getMint(setup.connection, derivativeMint)).supply;
+  }
+
+  async function lockAndGetMinted(
+    tokenMint: anchor.web3.PublicKey,
+    amount: anchor.BN | number = lockAmount,
+    accounts: object = {}
+  ) {
+    const supplyBefore = await derivativeSupply(tokenMint);
+    await setup.program.methods
+      .lock(new anchor.BN(amount))
+      .accounts({
+        ...(await setup.tokenOpAccounts(tokenMint, setup.user.publicKey)),
+        ...accounts,
+      })
+      .signers([setup.user])
+      .rpc();
+    return (await derivativeSupply(tokenMint)) - supplyBefore;
+  }
+
+  async function unlockAndGetReleased(
+    tokenMint: anchor.web3.PublicKey,
+    amount: number
+  ) {
+    const userAta = await setup.getTokenATA(tokenMint, setup.user.publicKey);
+    await setup.program.methods
+      .unlock(new anchor.BN(amount))
+      .accounts(await setup.tokenOpAccounts(tokenMint, setup.user.publicKey))
+      .signers([setup.user])
+      .rpc();
+    const userAtaAfter = await setup.getTokenATA(
+      tokenMint,
+      setup.user.publicKey
+    );
+    return userAtaAfter.amount - userAta.amount;
+  }
+
+  it("Per-Token Fee Override", async () => {
+    const tokenMint = await setup.createTestToken(decimals, 2 * lockAmount);
+    await setup.registerToken(tokenMint);
+
+    await setTokenFeeOverride(tokenMint, { ...noFee, feeBps: 100 });
+    const tokenInfo = await setup.program.account.tokenInfo.fetch(
+      setup.getTokenInfoPDA(tokenMint).pda
+    );
+    assert(tokenInfo.lockFeeOverride.feeBps == 100, "Wrong Lock Fee Override");
+    assert(tokenInfo.unlockFeeOverride === null, "Unlock Fee Override Set");
+    assert(
+      (await lockAndGetMinted(tokenMint)) == BigInt(lockAmount * 0.99),
+      "Fee Override Not Applied"
+    );
+
+    // clearing the override falls back to the global fee
+    await setTokenFeeOverride(tokenMint, null);
+    assert(
+      (await lockAndGetMinted(tokenMint)) ==
+        BigInt(lockAmount - setup.calculateFee(lockAmount)),
+      "Global Fee Not Applied"
+    );
+  });
+
+  it("Separate Lock And Unlock Fees", async () => {
+    const tokenMint = await setup.createTestToken(decimals, lockAmount);
+    await setup.registerToken(tokenMint);
+
+    await updateGlobalConfig({ lockFee: noFee });
+    try {
+      assert(
+        (await lockAndGetMinted(tokenMint)) == BigInt(lockAmount),
+        "Lock Fee Charged"
+      );
+      assert(
+        (await unlockAndGetReleased(tokenMint, lockAmount)) ==
+          BigInt(lockAmount - setup.calculateFee(lockAmount)),
+        "Unlock Fee Not Charged"
+      );
+    } finally {
+      await updateGlobalConfig({});
+    }
+  });
+
+  it("Weighted Fee Recipients", async () => {
+    const tokenMint = await setup.createTestToken(decimals, lockAmount);
+    await setup.registerToken(tokenMint);
+
+    const treasury = anchor.web3.Keypair.generate().publicKey;
+    const recipients = [
+      setup.developer.publicKey,
+      setup.founder.publicKey,
+      treasury,
+    ];
+    const weights = [5_000, 3_000, 2_000];
+    const balances = async () =>
+      await Promise.all(
+        recipients.map((recipient) => setup.feeBalance(tokenMint, recipient))
+      );
+
+    await updateGlobalConfig({
+      feeRecipients: recipients.map((wallet, i) => ({
+        wallet,
+        weightBps: weights[i],
+      })),
+    });
+    const before = await balances();
+    try {
+      await lockAndGetMinted(tokenMint);
+    } finally {
+      await updateGlobalConfig({});
+    }
+    const after = await balances();
+
+    const fee = setup.calculateFee(lockAmount);
+    for (let i = 0; i < recipients.length; i++) {
+      assert(
+        after[i] - before[i] == BigInt((fee * weights[i]) / 10_000),
+        `Wrong Fee Share For Recipient ${i}`
+      );
+    }
+
+    // dropped from the config, the treasury still keeps what it accrued
+    await setup.claimFees(tokenMint, treasury);
+    assert(
+      (await setup.getTokenATA(tokenMint, treasury)).amount == after[2],
+      "Wrong Treasury Claim"
+    );
+    await expectTwosideError(
+      setup.claimFees(tokenMint, treasury),
+      "NoFeesToClaim"
+    );
+  });
+
+  it("Fee Exempt Wallet", async () => {
+    const tokenMint = await setup.createTestToken(decimals, 2 * lockAmount);
+    await setup.registerToken(tokenMint);
+
+    const setFeeExemption = (
+      exempt: boolean,
+      admin: anchor.web3.Keypair = setup.admin
+    ) =>
+      setup.program.methods
+        .setFeeExemption(setup.user.publicKey, exempt)
+        .accounts({
+          admin: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+
+    await expectTwosideError(
+      setFeeExemption(true, setup.payer),
+      "Unauthorized"
+    );
+
+    await setFeeExemption(true);
+    try {
+      const feesBefore = await setup.feeBalance(
+        tokenMint,
+        setup.founder.publicKey
+      );
+      assert(
+        (await lockAndGetMinted(tokenMint)) == BigInt(lockAmount),
+        "Exempt Lock Charged"
+      );
+      assert(
+        (await unlockAndGetReleased(tokenMint, lockAmount)) ==
+          BigInt(lockAmount),
+        "Exempt Unlock Charged"
+      );
+      const signature = await setup.program.methods
+        .lock(new anchor.BN(lockAmount))
+        .accounts(await setup.tokenOpAccounts(tokenMint, setup.user.publicKey))
+        .signers([setup.user])
+        .rpc({ commitment: "confirmed" });
+      assert(
+        !(await setup.getEvents(signature)).some(
+          (event) => event.name == "feeAccrued"
+        ),
+        "Empty Fee Accrual Emitted"
+      );
+      assert(
+        (await setup.feeBalance(tokenMint, setup.founder.publicKey)) ==
+          feesBefore,
+        "Exempt Fees Accrued"
+      );
+    } finally {
+      await setFeeExemption(false);
+    }
+
+    assert(
+      (await lockAndGetMinted(tokenMint)) ==
+        BigInt(lockAmount - setup.calculateFee(lockAmount)),
+      "Revoked Exemption Not Charged"
+    );
+  });
+
+  it("Caller Fee Exemption Needs A Direct CPI", async () => {
+    const tokenMint = await setup.createTestToken(decimals, 2 * lockAmount);
+    await setup.registerToken(tokenMint);
+    const forwarder = setup.forwarder.programId;
+    const [callerFeeExemption] = anchor.web3.PublicKey.findProgramAddressSync(
+      [setup.FEE_EXEMPTION_STATIC_SEED, forwarder.toBuffer()],
+      setup.program.programId
+    );
+
+    const lockIx = await setup.program.methods
+      .lock(new anchor.BN(lockAmount))
+      .accounts({
+        ...(await setup.tokenOpAccounts(tokenMint, setup.user.publicKey)),
+        callerFeeExemption,
+      })
+      .instruction();
+    const forwardIx = (
+      targetProgram: anchor.web3.PublicKey,
+      ix: anchor.web3.TransactionInstruction
+    ) =>
+      setup.forwarder.methods
+        .forward(ix.data)
+        .accounts({ targetProgram })
+        .remainingAccounts(ix.keys)
+        .instruction();
+    const lockThrough = async (ix: anchor.web3.TransactionInstruction) => {
+      const supplyBefore = await derivativeSupply(tokenMint);
+      const tx = new anchor.web3.Transaction().add(
+        anchor.web3.ComputeBudgetProgram.setComputeUnitLimit({
+          units: 400_000,
+        }),
+        ix
+      );
+      await setup.provider.sendAndConfirm(tx, [setup.user]);
+      return (await derivativeSupply(tokenMint)) - supplyBefore;
+    };
+
+    const setFeeExemption = (exempt: boolean) =>
+      setup.program.methods
+        .setFeeExemption(forwarder, exempt)
+        .accounts({
+          admin: setup.admin.publicKey,
+        })
+        .signers([setup.admin])
+        .rpc();
+
+    await setFeeExemption(true);
+    try {
+      const directIx = await forwardIx(setup.program.programId, lockIx);
+      assert(
+        (await lockThrough(directIx)) == BigInt(lockAmount),
+        "Direct Caller Charged"
+      );
+
+      // the exempt forwarder is still the top level program, but twoside is
+      // now invoked by a nested forwarder call rather than by it directly
+      const nestedIx = await forwardIx(forwarder, directIx);
+      assert(
+        (await lockThrough(nestedIx)) ==
+          BigInt(lockAmount - setup.calculateFee(lockAmount)),
+        "Intermediate Caller Exempted"
+      );
+    } finally {
+      await setFeeExemption(false);
+    }
+  });
+
+  it("Referral Fee Share", async () => {
+    const tokenMint = await setup.createTestToken(decimals, lockAmount);
+    await setup.registerToken(tokenMint);
+
+    const referralFeeBps = 2_000;
+    const referrer = anchor.web3.Keypair.generate().publicKey;
+    const referrerAta = await setup.getTokenATA(tokenMint, referrer);
+    const referrerStats = setup.getReferrerStatsPDA(referrer, tokenMint).pda;
+    const referrerEntry = setup.getReferrerEntryPDA(referrer).pda;
+    const baseAccounts = await setup.tokenOpAccounts(
+      tokenMint,
+      setup.user.publicKey
+    );
+    const lockWithReferrer = (accounts: object) =>
+      setup.program.methods
+        .lock(new anchor.BN(lockAmount))
+        .accounts({ ...baseAccounts, ...accounts })
+        .signers([setup.user])
+        .rpc();
+
+    const setReferrerStatus = (
+      subject: anchor.web3.PublicKey,
+      allowed: boolean,
+      admin: anchor.web3.Keypair = setup.admin
+    ) =>
+      setup.program.methods
+        .setReferrerStatus(subject, allowed)
+        .accounts({
+          admin: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+
+    await updateGlobalConfig({ referralFeeBps });
+    try {
+      await expectTwosideError(
+        lockWithReferrer({
+          referrer,
+          referrerTokenAccount: referrerAta.address,
+        }),
+        "InvalidReferrerAccounts"
+      );
+      const userAta = await setup.getTokenATA(tokenMint, setup.user.publicKey);
+      await expectTwosideError(
+        lockWithReferrer({
+          referrer: setup.user.publicKey,
+          referrerTokenAccount: userAta.address,
+          referrerStats: setup.getReferrerStatsPDA(
+            setup.user.publicKey,
+            tokenMint
+          ).pda,
+          referrerEntry: setup.getReferrerEntryPDA(setup.user.publicKey).pda,
+        }),
+        "SelfReferral"
+      );
+
+      // a wallet the signer controls is no referrer until the admin lists it
+      const referral = {
+        referrer,
+        referrerTokenAccount: referrerAta.address,
+        referrerStats,
+        referrerEntry,
+      };
+      await expectTwosideError(
+        lockWithReferrer(referral),
+        "ReferrerNotAllowed"
+      );
+      await expectTwosideError(
+        setReferrerStatus(referrer, true, setup.payer),
+        "Unauthorized"
+      );
+      await setReferrerStatus(referrer, true);
+
+      const feesBefore =
+        (await setup.feeBalance(tokenMint, setup.developer.publicKey)) +
+        (await setup.feeBalance(tokenMint, setup.founder.publicKey));
+      await lockWithReferrer(referral);
+      const feesAfter =
+        (await setup.feeBalance(tokenMint, setup.developer.publicKey)) +
+        (await setup.feeBalance(tokenMint, setup.founder.publicKey));
+
+      const fee = setup.calculateFee(lockAmount);
+      const referralFee = BigInt((fee * referralFeeBps) / 10_000);
+      assert(
+        (await setup.getTokenATA(tokenMint, referrer)).amount == referralFee,
+        "Wrong Referral Fee Paid"
+      );
+      assert(
+        feesAfter - feesBefore == BigInt(fee) - referralFee,
+        "Referral Fee Not Carved Out Of Recipient Split"
+      );
+
+      const stats = await setup.program.account.referrerStats.fetch(
+        referrerStats
+      );
+      assert(stats.referrer.equals(referrer), "Wrong Stats Referrer");
+      assert(stats.referralCount.eqn(1), "Wrong Referral Count");
+      assert(stats.referredVolume.eqn(lockAmount), "Wrong Referred Volume");
+      assert(
+        stats.feesEarned.toString() == referralFee.toString(),
+        "Wrong Fees Earned"
+      );
+    } finally {
+      await updateGlobalConfig({});
+    }
+  });
+
+  async function setFeeTiers(
+    tokenMint: anchor.web3.PublicKey,
+    basis: object,
+    tiers: { threshold: anchor.BN; feeBps: number }[],
+    admin: anchor.web3.Keypair = setup.admin
+  ) {
+    return await setup.program.methods
+      .setFeeTiers(basis, tiers)
+      .accounts({
+        admin: admin.publicKey,
+        tokenMint: tokenMint,
+      })
+      .signers([admin])
+      .rpc();
+  }
+
+  const feeAt = (amount: number, feeBps: number) =>
+    Math.floor((amount * feeBps + 5_000) / 10_000);
+
+  it("Amount Based Fee Tiers", async () => {
+    const tokenMint = await setup.createTestToken(decimals, lockAmount);
+    await setup.registerToken(tokenMint);
+
+    await setFeeTiers(tokenMint, { amount: {} }, [
+      { threshold: new anchor.BN(100 * 10 ** decimals), feeBps: 30 },
+      { threshold: new anchor.BN(500 * 10 ** decimals), feeBps: 10 },
+    ]);
+
+    for (const [amount, feeBps] of [
+      [50 * 10 ** decimals, setup.fee_bps],
+      [200 * 10 ** decimals, 30],
+      [600 * 10 ** decimals, 10],
+    ]) {
+      assert(
+        (await lockAndGetMinted(tokenMint, amount)) ==
+          BigInt(amount - feeAt(amount, feeBps)),
+        `Wrong Tier For ${amount}`
+      );
+    }
+
+    // tiers only discount, a leg made cheaper than a tier keeps its own rate
+    await setTokenFeeOverride(tokenMint, { ...noFee, feeBps: 20 });
+    const amount = 200 * 10 ** decimals;
+    assert(
+      (await lockAndGetMinted(tokenMint, amount)) ==
+        BigInt(amount - feeAt(amount, 20)),
+      "Tier Above Leg Rate Applied"
+    );
+  });
+
+  it("Volume Based Fee Tiers", async () => {
+    const tokenMint = await setup.createTestToken(decimals, lockAmount);
+    await setup.registerToken(tokenMint);
+
+    const amount = 200 * 10 ** decimals;
+    await setFeeTiers(tokenMint, { volume: {} }, [
+      { threshold: new anchor.BN(300 * 10 ** decimals), feeBps: 20 },
+    ]);
+
+    const userVolume = setup.getUserVolumePDA(
+      setup.user.publicKey,
+      tokenMint
+    ).pda;
+    const lockWithVolume = async () => {
+      const supplyBefore = await derivativeSupply(tokenMint);
+      await setup.program.methods
+        .lock(new anchor.BN(amount))
+        .accounts({
+          ...(await setup.tokenOpAccounts(tokenMint, setup.user.publicKey)),
+          userVolume,
+        })
+        .signers([setup.user])
+        .rpc();
+      return (await derivativeSupply(tokenMint)) - supplyBefore;
+    };
+
+    assert(
+      (await lockWithVolume()) ==
+        BigInt(amount - feeAt(amount, setup.fee_bps)),
+      "Tier Applied Below Volume Threshold"
+    );
+    // without the volume account the signer gets no volume discount
+    assert(
+      (await lockAndGetMinted(tokenMint, amount)) ==
+        BigInt(amount - feeAt(amount, setup.fee_bps)),
+      "Tier Applied Without Volume Account"
+    );
+    assert(
+      (await lockWithVolume()) == BigInt(amount - feeAt(amount, 20)),
+      "Tier Not Applied Above Volume Threshold"
+    );
+
+    const volume = await setup.program.account.userVolume.fetch(userVolume);
+    assert(volume.volume.eqn(2 * amount), "Wrong Recorded Volume");
+  });
+
+  it("Invalid Fee Tiers", async () => {
+    const tokenMint = await setup.createTestToken(decimals, lockAmount);
+    await setup.registerToken(tokenMint);
+    const tier = (threshold: number, feeBps: number) => ({
+      threshold: new anchor.BN(threshold),
+      feeBps,
+    });
+
+    await expectTwosideError(
+      setFeeTiers(tokenMint, { amount: {} }, [tier(1, 10)], setup.payer),
+      "Unauthorized"
+    );
+    await expectTwosideError(
+      setFeeTiers(tokenMint, { amount: {} }, [tier(1, 10), tier(2, 20)]),
+      "InvalidFeeTiers"
+    );
+    await expectTwosideError(
+      setFeeTiers(tokenMint, { amount: {} }, [tier(2, 20), tier(1, 10)]),
+      "InvalidFeeTiers"
+    );
+    await expectTwosideError(
+      setFeeTiers(
+        tokenMint,
+        { amount: {} },
+        [1, 2, 3, 4, 5].map((threshold) => tier(threshold, 10))
+      ),
+      "InvalidFeeTiers"
+    );
+    await expectTwosideError(
+      setFeeTiers(tokenMint, { amount: {} }, [tier(1, 1001)]),
+      "FeeTooHigh"
+    );
+    await expectTwosideError(
+      setFeeTiers(tokenMint, { amount: {} }, [tier(1, setup.fee_bps + 1)]),
+      "FeeTierAboveBaseRate"
+    );
+  });
+
+  it("SOL Fee Mode", async () => {
+    // Pyth fixtures from Anchor.toml, the token at 2 USD and SOL at 100 USD
+    const tokenPriceFeed = new anchor.web3.PublicKey(
+      "9suJdbRE6wJZSiwBfjYBgYnsFDUG7S9ME797Cec4ooXW"
+    );
+    const solPriceFeed = new anchor.web3.PublicKey(
+      "HK6oY8B5n1oTv9ySEw4Uoqd37U4NhzmC7vB5qbCQMkf3"
+    );
+    const staleSolPriceFeed = new anchor.web3.PublicKey(
+      "AVmfPdwDhKeonjP5d2Utv9gvLCiyAn6TYhmQoyvEXUPR"
+    );
+    // same price with a 10% confidence interval
+    const wideTokenPriceFeed = new anchor.web3.PublicKey(
+      "Hi66vPeaY9W4ph523e78AVYgGYFnpmYS4mQLKoUZt7wh"
+    );
+    const tokenMint = await setup.createTestToken(decimals, 2 * lockAmount);
+    await setup.registerToken(tokenMint);
+    const { pda: feeVault } = setup.getFeeVault(tokenMint);
+
+    const setTokenSolFee = (
+      solFee: object | null,
+      admin: anchor.web3.Keypair = setup.admin
+    ) =>
+      setup.program.methods
+        .setTokenSolFee(solFee)
+        .accounts({
+          admin: admin.publicKey,
+          tokenInfo: setup.getTokenInfoPDA(tokenMint).pda,
+        })
+        .signers([admin])
+        .rpc();
+    const lamportsOf = (wallet: anchor.web3.PublicKey) =>
+      setup.connection.getBalance(wallet);
+    const lamportBalance = async (wallet: anchor.web3.PublicKey) => {
+      const vault = await setup.program.account.feeVault.fetch(feeVault);
+      const balance = vault.balances.find((entry) =>
+        entry.wallet.equals(wallet)
+      );
+      return balance ? balance.lamports.toNumber() : 0;
+    };
+
+    await expectTwosideError(
+      setTokenSolFee({ flat: { lamports: new anchor.BN(1) } }, setup.payer),
+      "Unauthorized"
+    );
+    await expectTwosideError(
+      setTokenSolFee({
+        flat: { lamports: new anchor.BN(anchor.web3.LAMPORTS_PER_SOL + 1) },
+      }),
+      "SolFeeTooHigh"
+    );
+    for (const maxPriceAge of [0, 3601]) {
+      await expectTwosideError(
+        setTokenSolFee({
+          priced: { tokenPriceFeed, solPriceFeed, maxPriceAge },
+        }),
+        "InvalidPriceAge"
+      );
+    }
+
+    const flatFee = 10_000;
+    await setTokenSolFee({ flat: { lamports: new anchor.BN(flatFee) } });
+    let userBefore = await lamportsOf(setup.user.publicKey);
+    assert(
+      (await lockAndGetMinted(tokenMint)) == BigInt(lockAmount),
+      "Token Fee Charged In SOL Mode"
+    );
+    assert(
+      userBefore - (await lamportsOf(setup.user.publicKey)) == flatFee,
+      "Flat SOL Fee Not Charged"
+    );
+    assert(
+      (await lamportBalance(setup.developer.publicKey)) == flatFee / 2,
+      "Wrong Accrued SOL Fee"
+    );
+
+    const developerBefore = await lamportsOf(setup.developer.publicKey);
+    await setup.claimFees(tokenMint, setup.developer.publicKey);
+    assert(
+      (await lamportsOf(setup.developer.publicKey)) - developerBefore ==
+        flatFee / 2,
+      "SOL Fee Not Claimed"
+    );
+    assert(
+      (await lamportBalance(setup.developer.publicKey)) == 0,
+      "SOL Fee Balance Not Cleared"
+    );
+
+    await setTokenSolFee({
+      priced: { tokenPriceFeed, solPriceFeed, maxPriceAge: 60 },
+    });
+    await expectTwosideError(lockAndGetMinted(tokenMint), "InvalidPriceFeed");
+    const priceFeeds = { tokenPriceFeed, solPriceFeed };
+    userBefore = await lamportsOf(setup.user.publicKey);
+    assert(
+      (await lockAndGetMinted(tokenMint, lockAmount, priceFeeds)) ==
+        BigInt(lockAmount),
+      "Token Fee Charged In Priced SOL Mode"
+    );
+    // a base unit of the token is worth 2 * 10^-6 USD, i.e. 20 lamports
+    assert(
+      userBefore - (await lamportsOf(setup.user.publicKey)) ==
+        setup.calculateFee(lockAmount) * 20,
+      "Priced SOL Fee Not Charged"
+    );
+
+    await setTokenSolFee({
+      priced: {
+        tokenPriceFeed,
+        solPriceFeed: staleSolPriceFeed,
+        maxPriceAge: 60,
+      },
+    });
+    await expectTwosideError(
+      lockAndGetMinted(tokenMint, lockAmount, {
+        tokenPriceFeed,
+        solPriceFeed: staleSolPriceFeed,
+      }),
+      "StalePrice"
+    );
+
+    await setTokenSolFee({
+      priced: {
+        tokenPriceFeed: wideTokenPriceFeed,
+        solPriceFeed,
+        maxPriceAge: 60,
+      },
+    });
+    await expectTwosideError(
+      lockAndGetMinted(tokenMint, lockAmount, {
+        tokenPriceFeed: wideTokenPriceFeed,
+        solPriceFeed,
+      }),
+      "InvalidOraclePrice"
+    );
+
+    await setTokenSolFee(null);
+    const tokenInfo = await setup.program.account.tokenInfo.fetch(
+      setup.getTokenInfoPDA(tokenMint).pda
+    );
+    assert(tokenInfo.solFee === null, "SOL Fee Not Cleared");
+  });
+
+  it("Fee Properties Across Decimals", async () => {
+    const feeBps = BigInt(30);
+    // deterministic LCG so failures are reproducible
+    let seed = BigInt(42);
+    const nextRandom = (bound: bigint) => {
+      seed = BigInt.asUintN(
+        64,
+        seed * BigInt("6364136223846793005") + BigInt("1442695040888963407")
+      );
+      return seed % bound;
+    };
+
+    for (const tokenDecimals of [0, 2, 6, 9]) {
+      const unit = BigInt(10 ** tokenDecimals);
+      // 0.01 and 2 whole tokens, at least one base unit
+      const minFee = unit >= BigInt(100) ? unit / BigInt(100) : BigInt(1);
+      const maxFee = BigInt(2) * unit;
+      const maxAmount = BigInt(1_000) * unit;
+
+      const tokenMint = await setup.createTestToken(
+        tokenDecimals,
+        Number(BigInt(5) * maxAmount)
+      );
+      await setup.registerToken(tokenMint);
+      await setTokenFeeOverride(tokenMint, {
+        feeBps: Number(feeBps),
+        minFee: new anchor.BN(minFee.toString()),
+        maxFee: new anchor.BN(maxFee.toString()),
+      });
+
+      // smallest lockable amount, fee at the cap, and random amounts between
+      const amounts = [minFee + BigInt(1), maxAmount];
+      for (let i = 0; i < 3; i++) {
+        amounts.push(minFee + BigInt(1) + nextRandom(maxAmount - minFee));
+      }
+
+      for (const amount of amounts) {
+        const minted = await lockAndGetMinted(
+          tokenMint,
+          new anchor.BN(amount.toString())
+        );
+        const fee = amount - minted;
+        const rounded = (amount * feeBps + BigInt(5_000)) / BigInt(10_000);
+        const expected =
+          rounded < minFee ? minFee : rounded > maxFee ? maxFee : rounded;
+        const context = `decimals ${tokenDecimals}, amount ${amount}`;
+
+        assert(fee == expected, `Wrong Fee For ${context}`);
+        assert(fee >= minFee && fee <= maxFee, `Fee Out Of Bounds: ${context}`);
+        assert(minted > BigInt(0), `Nothing Minted For ${context}`);
+      }
+    }
+  });
+
+  it("Invalid Fee Overrides", async () => {
+    const tokenMint = await setup.createTestToken(decimals, lockAmount);
+    await setup.registerToken(tokenMint);
+
+    await expectTwosideError(
+      setTokenFeeOverride(tokenMint, noFee, null, setup.payer),
+      "Unauthorized"
+    );
+    await expectTwosideError(
+      setTokenFeeOverride(tokenMint, null, { ...noFee, feeBps: 1001 }),
+      "FeeTooHigh"
+    );
+    await expectTwosideError(
+      setTokenFeeOverride(tokenMint, {
+        ...noFee,
+        minFee: new anchor.BN(10),
+        maxFee: new anchor.BN(5),
+      }),
+      "InvalidFeeBounds"
+    );
+
+    // a one unit minimum fee swallows a one unit lock
+    await setTokenFeeOverride(tokenMint, {
+      ...noFee,
+      minFee: new anchor.BN(1),
+    });
+    await expectTwosideError(
+      setup.program.methods
+        .lock(new anchor.BN(1))
+        .accounts(await setup.tokenOpAccounts(tokenMint, setup.user.publicKey))
+        .signers([setup.user])
+        .rpc(),
+      "AmountInsufficientAfterFee"
+    );
+  });
+});
@@ -1,46 +1,352 @@
 import { assert } from "chai";
-import { setup } from "./setup";
+import * as anchor from "@coral-xyz/anchor";
+import { expectTwosideError, setup, U64_MAX } from "./setup";
 
 describe("Program Initialization", () => {
   before(async () => {
     await setup.initialize();
   });
 
+  it("Initialization By Non-Upgrade Authority", async () => {
+    await expectTwosideError(
+      setup.program.methods
+        .initializeProgram(setup.user.publicKey, setup.user.publicKey)
+        .accounts({
+          signer: setup.user.publicKey,
+          programData: setup.programDataPDA,
+        })
+        .signers([setup.user])
+        .rpc(),
+      "Unauthorized"
+    );
+
+    const globalInfoAccount = await setup.connection.getAccountInfo(
+      setup.globalInfoPDA
+    );
+    assert(globalInfoAccount === null, "Global Info Created By Hijacker");
+  });
+
   it("Normal Initialization", async () => {
     await setup.program.methods
       .initializeProgram(setup.developer.publicKey, setup.founder.publicKey)
       .accounts({
-        signer: setup.user.publicKey,
+        signer: setup.admin.publicKey,
+        programData: setup.programDataPDA,
       })
-      .signers([setup.user])
+      .signers([setup.admin])
       .rpc();
 
     const globalInfoAccount = await setup.program.account.globalInfo.fetch(
       setup.globalInfoPDA
     );
 
-    const developerWalletStr = globalInfoAccount.developerWallet.toBase58();
-    const founderWalletStr = globalInfoAccount.founderWallet.toBase58();
-    const feePercentageNum = globalInfoAccount.feePercentage;
-    const feeDividerNum = globalInfoAccount.feePercentageDivider;
-    const developerFeeShareNum = globalInfoAccount.developerFeeShare;
-    const founderFeeShareNum = globalInfoAccount.founderFeeShare;
-    const minFee = globalInfoAccount.minFee;
-    const minFeeForDistribution = globalInfoAccount.minFeeForDistribution;
+    const [developerRecipient, founderRecipient] =
+      globalInfoAccount.feeRecipients;
 
     assert(
-      setup.founder.publicKey.toString() == founderWalletStr,
-      "Wrong Founder Address Set"
+      globalInfoAccount.feeRecipients.length == 2,
+      "Wrong Fee Recipient Count"
     );
     assert(
-      setup.developer.publicKey.toString() == developerWalletStr,
+      setup.developer.publicKey.toString() ==
+        developerRecipient.wallet.toString(),
       "Wrong Developer Address Set"
     );
-    assert(feePercentageNum == 5, "Wrong feePercentage Set");
-    assert(feeDividerNum == 1000, "Wrong feePercentageDivider Set");
-    assert(developerFeeShareNum == 50, "Wrong developerFeeShare Set");
-    assert(founderFeeShareNum == 50, "Wrong founderFeeShare Set");
-    assert(minFee == 2, "Wrong minFee Set");
-    assert(minFeeForDistribution == 2, "Wrong minFeeForDistribution Set");
+    assert(
+      setup.founder.publicKey.toString() == founderRecipient.wallet.toString(),
+      "Wrong Founder Address Set"
+    );
+    for (const feeConfig of [
+      globalInfoAccount.lockFee,
+      globalInfoAccount.unlockFee,
+    ]) {
+      assert(feeConfig.feeBps == 50, "Wrong feeBps Set");
+      assert(feeConfig.minFee.eqn(0), "Wrong minFee Set");
+      assert(feeConfig.maxFee.eq(U64_MAX), "Wrong maxFee Set");
+    }
+    assert(developerRecipient.weightBps == 5000, "Wrong Developer Weight Set");
+    assert(founderRecipient.weightBps == 5000, "Wrong Founder Weight Set");
+    assert(globalInfoAccount.feeRemainderIndex == 1, "Wrong Remainder Index");
+    assert(
+      globalInfoAccount.admin.toString() == setup.admin.publicKey.toString(),
+      "Wrong Admin Set"
+    );
+  });
+
+  it("Admin Config Update", async () => {
+    const config = {
+      ...setup.defaultGlobalConfig(),
+      unlockFee: { ...setup.defaultFeeConfig(), feeBps: 100 },
+      feeRecipients: [
+        { wallet: setup.developer.publicKey, weightBps: 6_000 },
+        { wallet: setup.founder.publicKey, weightBps: 4_000 },
+      ],
+    };
+
+    await setup.program.methods
+      .updateGlobalConfig(config)
+      .accounts({
+        admin: setup.admin.publicKey,
+      })
+      .signers([setup.admin])
+      .rpc();
+
+    let globalInfoAccount = await setup.program.account.globalInfo.fetch(
+      setup.globalInfoPDA
+    );
+    assert(
+      globalInfoAccount.unlockFee.feeBps == 100,
+      "Wrong Unlock feeBps Set"
+    );
+    assert(globalInfoAccount.lockFee.feeBps == 50, "Wrong Lock feeBps Set");
+    assert(
+      globalInfoAccount.feeRecipients[0].weightBps == 6000,
+      "Wrong Developer Weight Set"
+    );
+    assert(
+      globalInfoAccount.feeRecipients[1].weightBps == 4000,
+      "Wrong Founder Weight Set"
+    );
+
+    // restore the defaults the lock and unlock suites rely on
+    await setup.program.methods
+      .updateGlobalConfig(setup.defaultGlobalConfig())
+      .accounts({
+        admin: setup.admin.publicKey,
+      })
+      .signers([setup.admin])
+      .rpc();
+
+    globalInfoAccount = await setup.program.account.globalInfo.fetch(
+      setup.globalInfoPDA
+    );
+    assert(globalInfoAccount.unlockFee.feeBps == 50, "Wrong feeBps Set");
+    assert(
+      globalInfoAccount.feeRecipients[0].weightBps == 5000,
+      "Wrong Developer Weight Set"
+    );
+  });
+
+  it("Non-Admin Config Update", async () => {
+    await expectTwosideError(
+      setup.program.methods
+        .updateGlobalConfig(setup.defaultGlobalConfig())
+        .accounts({
+          admin: setup.payer.publicKey,
+        })
+        .signers([setup.payer])
+        .rpc(),
+      "Unauthorized"
+    );
+  });
+
+  it("Invalid Config Update", async () => {
+    await expectTwosideError(
+      setup.program.methods
+        .updateGlobalConfig({
+          ...setup.defaultGlobalConfig(),
+          feeRecipients: [
+            { wallet: setup.developer.publicKey, weightBps: 6_000 },
+            { wallet: setup.founder.publicKey, weightBps: 5_000 },
+          ],
+        })
+        .accounts({
+          admin: setup.admin.publicKey,
+        })
+        .signers([setup.admin])
+        .rpc(),
+      "InvalidFeeShares"
+    );
+    await expectTwosideError(
+      setup.program.methods
+        .updateGlobalConfig({
+          ...setup.defaultGlobalConfig(),
+          feeRemainderIndex: 2,
+        })
+        .accounts({
+          admin: setup.admin.publicKey,
+        })
+        .signers([setup.admin])
+        .rpc(),
+      "InvalidFeeRecipients"
+    );
+    await expectTwosideError(
+      setup.program.methods
+        .updateGlobalConfig({
+          ...setup.defaultGlobalConfig(),
+          feeRecipients: [],
+          feeRemainderIndex: 0,
+        })
+        .accounts({
+          admin: setup.admin.publicKey,
+        })
+        .signers([setup.admin])
+        .rpc(),
+      "InvalidFeeRecipients"
+    );
+    await expectTwosideError(
+      setup.program.methods
+        .updateGlobalConfig({
+          ...setup.defaultGlobalConfig(),
+          referralFeeBps: 10_001,
+        })
+        .accounts({
+          admin: setup.admin.publicKey,
+        })
+        .signers([setup.admin])
+        .rpc(),
+      "InvalidReferralFee"
+    );
+    await expectTwosideError(
+      setup.program.methods
+        .updateGlobalConfig({
+          ...setup.defaultGlobalConfig(),
+          lockFee: {
+            ...setup.defaultFeeConfig(),
+            minFee: new anchor.BN(10),
+            maxFee: new anchor.BN(5),
+          },
+        })
+        .accounts({
+          admin: setup.admin.publicKey,
+        })
+        .signers([setup.admin])
+        .rpc(),
+      "InvalidFeeBounds"
+    );
+    await expectTwosideError(
+      setup.program.methods
+        .updateGlobalConfig({
+          ...setup.defaultGlobalConfig(),
+          unlockFee: { ...setup.defaultFeeConfig(), feeBps: 1001 },
+        })
+        .accounts({
+          admin: setup.admin.publicKey,
+        })
+        .signers([setup.admin])
+        .rpc(),
+      "FeeTooHigh"
+    );
+  });
+
+  it("Two-Step Admin Transfer", async () => {
+    const newAdmin = setup.payer;
+
+    await setup.program.methods
+      .proposeAdmin(newAdmin.publicKey)
+      .accounts({
+        admin: setup.admin.publicKey,
+      })
+      .signers([setup.admin])
+      .rpc();
+
+    let globalInfoAccount = await setup.program.account.globalInfo.fetch(
+      setup.globalInfoPDA
+    );
+    assert(
+      globalInfoAccount.pendingAdmin.toString() ==
+        newAdmin.publicKey.toString(),
+      "Wrong Pending Admin Set"
+    );
+    assert(
+      globalInfoAccount.admin.toString() == setup.admin.publicKey.toString(),
+      "Admin Changed Before Acceptance"
+    );
+
+    await expectTwosideError(
+      setup.program.methods
+        .acceptAdmin()
+        .accounts({
+          pendingAdmin: setup.developer.publicKey,
+        })
+        .signers([setup.developer])
+        .rpc(),
+      "Unauthorized"
+    );
+
+    await setup.program.methods
+      .acceptAdmin()
+      .accounts({
+        pendingAdmin: newAdmin.publicKey,
+      })
+      .signers([newAdmin])
+      .rpc();
+
+    globalInfoAccount = await setup.program.account.globalInfo.fetch(
+      setup.globalInfoPDA
+    );
+    assert(
+      globalInfoAccount.admin.toString() == newAdmin.publicKey.toString(),
+      "Wrong Admin Set"
+    );
+    assert(
+      globalInfoAccount.pendingAdmin.toString() ==
+        anchor.web3.PublicKey.default.toString(),
+      "Pending Admin Not Cleared"
+    );
+
+    // hand the authority back so the remaining suites keep using setup.admin
+    await setup.program.methods
+      .proposeAdmin(setup.admin.publicKey)
+      .accounts({
+        admin: newAdmin.publicKey,
+      })
+      .signers([newAdmin])
+      .rpc();
+    await setup.program.methods
+      .acceptAdmin()
+      .accounts({
+        pendingAdmin: setup.admin.publicKey,
+      })
+      .signers([setup.admin])
+      .rpc();
+  });
+
+  it("Cancel Admin Transfer", async () => {
+    await expectTwosideError(
+      setup.program.methods
+        .cancelAdminTransfer()
+        .accounts({
+          admin: setup.admin.publicKey,
+        })
+        .signers([setup.admin])
+        .rpc(),
+      "NoPendingAdmin"
+    );
+
+    await setup.program.methods
+      .proposeAdmin(setup.payer.publicKey)
+      .accounts({
+        admin: setup.admin.publicKey,
+      })
+      .signers([setup.admin])
+      .rpc();
+    await setup.program.methods
+      .cancelAdminTransfer()
+      .accounts({
+        admin: setup.admin.publicKey,
+      })
+      .signers([setup.admin])
+      .rpc();
+
+    const globalInfoAccount = await setup.program.account.globalInfo.fetch(
+      setup.globalInfoPDA
+    );
+    assert(
+      globalInfoAccount.pendingAdmin.toString() ==
+        anchor.web3.PublicKey.default.toString(),
+      "Pending Admin Not Cleared"
+    );
+
+    await expectTwosideError(
+      setup.program.methods
+        .acceptAdmin()
+        .accounts({
+          pendingAdmin: setup.payer.publicKey,
+        })
+        .signers([setup.payer])
+        .rpc(),
+      "Unauthorized"
+    );
   });
 });
@@ -71,15 +71,6 @@ describe("Token Locking", () => {
         ataBump: vaultAtaBump,
       } = setup.getTokenVault(tokenMint);
 
-      let founderAta = await setup.getTokenATA(
-        tokenMint,
-        setup.founder.publicKey
-      );
-      let developerAta = await setup.getTokenATA(
-        tokenMint,
-        setup.developer.publicKey
-      );
-
       await splToken.mintTo(
         setup.connection,
         setup.payer,
@@ -102,16 +93,36 @@ describe("Token Locking", () => {
       const { pda: derivativeAuthorityPDA, bump: derivativeAuthorityBump } =
         setup.getDerivativeAuthority(tokenMint);
 
+      await setup.program.methods
+        .registerToken()
+        .accounts({
+          tokenProgram: splToken.TOKEN_PROGRAM_ID,
+          derivativeTokenProgram: splToken.TOKEN_PROGRAM_ID,
+          tokenMint: tokenMint,
+          tokenMetadata: tokenMetadataPDA,
+          derivativeMetadata: derivativeMetadataPDA,
+          signer: setup.user.publicKey,
+          mplTokenMetadataProgram: MPL_TOKEN_METADATA_PROGRAM_ID,
+        })
+        .signers([setup.user])
+        .rpc();
+
       const tx = await setup.program.methods
         .lock(new anchor.BN(lockAmount))
         .accounts({
+          tokenProgram: splToken.TOKEN_PROGRAM_ID,
+          derivativeTokenProgram: splToken.TOKEN_PROGRAM_ID,
           tokenMint: tokenMint,
-          tokenMetadata: tokenMetadataPDA,
           signer: setup.user.publicKey,
           signerTokenAta: userTokenAta.address,
-          developerAta: developerAta.address,
-          founderAta: founderAta.address,
-          mplTokenMetadataProgram: MPL_TOKEN_METADATA_PROGRAM_ID,
+          callerFeeExemption: null,
+          tokenPriceFeed: null,
+          solPriceFeed: null,
+          referrer: null,
+          referrerTokenAccount: null,
+          referrerStats: null,
+          referrerEntry: null,
+          userVolume: null,
         })
         .signers([setup.user])
         .rpc();
@@ -163,19 +174,15 @@ describe("Token Locking", () => {
 
       const feeShare = (lockAmount * 5) / 2000;
 
-      developerAta = await setup.getTokenATA(
-        tokenMint,
-        setup.developer.publicKey
-      );
       assert(
-        developerAta.amount == BigInt(feeShare),
-        "Wrong Developer ATA Balance"
+        (await setup.feeBalance(tokenMint, setup.developer.publicKey)) ==
+          BigInt(feeShare),
+        "Wrong Developer Fee Balance"
       );
-
-      founderAta = await setup.getTokenATA(tokenMint, setup.founder.publicKey);
       assert(
-        founderAta.amount == BigInt(feeShare),
-        "Wrong Developer ATA Balance"
+        (await setup.feeBalance(tokenMint, setup.founder.publicKey)) ==
+          BigInt(feeShare),
+        "Wrong Founder Fee Balance"
       );
 
       const derivativeMetadataAccount = await setup.umi.rpc.getAccount(
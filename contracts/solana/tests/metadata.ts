@@ -0,0 +1,118 @@
+import { assert } from "chai";
+import * as anchor from "@coral-xyz/anchor";
+import { deserializeMetadata } from "@metaplex-foundation/mpl-token-metadata";
+import { fromWeb3JsPublicKey } from "@metaplex-foundation/umi-web3js-adapters";
+import { RpcAccount } from "@metaplex-foundation/umi";
+import { expectTwosideError, setup } from "./setup";
+
+describe("Derivative Metadata Sources", () => {
+  const decimals = 9;
+  const lockAmount = 10 * 10 ** decimals;
+
+  async function fetchDerivativeMetadata(tokenMint: anchor.web3.PublicKey) {
+    const { pda: derivativeMint } = setup.getDerivativeMint(tokenMint);
+    const derivativeMetadataAccount = await setup.umi.rpc.getAccount(
+      fromWeb3JsPublicKey(setup.getTokenMetadataPDA(derivativeMint).pda)
+    );
+    return deserializeMetadata(derivativeMetadataAccount as RpcAccount);
+  }
+
+  it("Register Token Without Metadata", async () => {
+    const tokenMint = await setup.createTestToken(
+      decimals,
+      lockAmount,
+      setup.user.publicKey,
+      false
+    );
+
+    // the canonical metadata PDA is passed even though it does not exist
+    await setup.registerToken(tokenMint);
+
+    const derivativeMetadata = await fetchDerivativeMetadata(tokenMint);
+    const mintAddress = tokenMint.toBase58();
+    assert(
+      derivativeMetadata.name ==
+        setup.getDerivativeName(mintAddress.slice(0, 8)),
+      "Wrong Fallback Derivative Name"
+    );
+    assert(
+      derivativeMetadata.symbol ==
+        setup.getDerivativeSymbol(mintAddress.slice(0, 4)),
+      "Wrong Fallback Derivative Symbol"
+    );
+  });
+
+  it("Register Token With Foreign Metadata", async () => {
+    const tokenMint = await setup.createTestToken(decimals, lockAmount);
+    const otherMint = await setup.createTestToken(decimals, lockAmount);
+
+    // metadata of another mint cannot stand in for the real one
+    await expectTwosideError(
+      setup.program.methods
+        .registerToken()
+        .accounts({
+          ...setup.registerAccounts(tokenMint, setup.user.publicKey),
+          tokenMetadata: setup.getTokenMetadataPDA(otherMint).pda,
+        })
+        .signers([setup.user])
+        .rpc(),
+      "InvalidTokenMetadataAddress"
+    );
+  });
+
+  it("Admin Label Overrides Metaplex Metadata", async () => {
+    const tokenMint = await setup.createTestToken(decimals, lockAmount);
+
+    await setup.program.methods
+      .setTokenLabel("Official", "OFC", "https://example.com/official.json")
+      .accounts({
+        admin: setup.admin.publicKey,
+        tokenMint: tokenMint,
+      })
+      .signers([setup.admin])
+      .rpc();
+
+    await setup.registerToken(tokenMint);
+
+    const derivativeMetadata = await fetchDerivativeMetadata(tokenMint);
+    assert(
+      derivativeMetadata.name == setup.getDerivativeName("Official"),
+      "Wrong Labelled Derivative Name"
+    );
+    assert(
+      derivativeMetadata.symbol == setup.getDerivativeSymbol("OFC"),
+      "Wrong Labelled Derivative Symbol"
+    );
+    assert(
+      derivativeMetadata.uri == "https://example.com/official.json",
+      "Wrong Labelled Derivative URI"
+    );
+  });
+
+  it("Invalid Token Labels", async () => {
+    const tokenMint = await setup.createTestToken(decimals, lockAmount);
+
+    await expectTwosideError(
+      setup.program.methods
+        .setTokenLabel("Official", "OFC", "")
+        .accounts({
+          admin: setup.payer.publicKey,
+          tokenMint: tokenMint,
+        })
+        .signers([setup.payer])
+        .rpc(),
+      "Unauthorized"
+    );
+    await expectTwosideError(
+      setup.program.methods
+        .setTokenLabel("Official", "TOOLONGSYMBOL", "")
+        .accounts({
+          admin: setup.admin.publicKey,
+          tokenMint: tokenMint,
+        })
+        .signers([setup.admin])
+        .rpc(),
+      "InvalidTokenLabel"
+    );
+  });
+});
@@ -0,0 +1,399 @@
+import { assert } from "chai";
+import * as crypto from "crypto";
+import * as fs from "fs";
+import * as anchor from "@coral-xyz/anchor";
+import * as splToken from "@solana/spl-token";
+import { FailedTransactionMetadata, LiteSVM } from "litesvm";
+import { expectTwosideError, setup, U64_MAX } from "./setup";
+import legacyMintSecret from "./fixtures/legacy_mint.json";
+import legacyHolderSecret from "./fixtures/legacy_holder.json";
+
+describe("Account Migration", () => {
+  const decimals = 6;
+  // mint of the v1 TokenInfo blob Anchor.toml loads into the validator, along
+  // with its derivative mint and a holder of the derivatives minted before the
+  // upgrade
+  const legacyMint = anchor.web3.Keypair.fromSecretKey(
+    Uint8Array.from(legacyMintSecret)
+  );
+  const legacyHolder = anchor.web3.Keypair.fromSecretKey(
+    Uint8Array.from(legacyHolderSecret)
+  );
+  const legacyAccountSize = 73;
+  const legacySupply = 1_000_000;
+
+  function migrateTokenInfo(
+    tokenMint: anchor.web3.PublicKey,
+    signer: anchor.web3.Keypair = setup.payer
+  ) {
+    return setup.program.methods
+      .migrateTokenInfo()
+      .accounts({
+        tokenProgram: splToken.TOKEN_PROGRAM_ID,
+        signer: signer.publicKey,
+        tokenMint: tokenMint,
+      })
+      .signers([signer])
+      .rpc();
+  }
+
+  async function tokenBalance(account: anchor.web3.PublicKey) {
+    return (await splToken.getAccount(setup.connection, account)).amount;
+  }
+
+  // The GlobalInfo PDA is taken on the validator by the time this runs, so the
+  // legacy global is migrated on a LiteSVM instance running the same program,
+  // deployed upgradeable so migrate_global_info can check the authority
+  function deployToSvm(svm: LiteSVM, upgradeAuthority: anchor.web3.PublicKey) {
+    const programId = setup.program.programId;
+    const loaderId = new anchor.web3.PublicKey(
+      "BPFLoaderUpgradeab1e11111111111111111111111"
+    );
+    const programData = Buffer.alloc(45);
+    programData.writeUInt32LE(3, 0); // UpgradeableLoaderState::ProgramData
+    programData.writeUInt8(1, 12); // Some(upgrade_authority_address)
+    upgradeAuthority.toBuffer().copy(programData, 13);
+    const programAccount = Buffer.alloc(36);
+    programAccount.writeUInt32LE(2, 0); // UpgradeableLoaderState::Program
+    setup.programDataPDA.toBuffer().copy(programAccount, 4);
+
+    svm.setAccount(setup.programDataPDA, {
+      executable: false,
+      owner: loaderId,
+      lamports: anchor.web3.LAMPORTS_PER_SOL,
+      data: Buffer.concat([
+        programData,
+        fs.readFileSync("target/deploy/solana.so"),
+      ]),
+    });
+    svm.setAccount(programId, {
+      executable: true,
+      owner: loaderId,
+      lamports: anchor.web3.LAMPORTS_PER_SOL,
+      data: programAccount,
+    });
+  }
+
+  function fundOnSvm(svm: LiteSVM, wallet: anchor.web3.PublicKey) {
+    svm.setAccount(wallet, {
+      executable: false,
+      owner: anchor.web3.SystemProgram.programId,
+      lamports: 10 * anchor.web3.LAMPORTS_PER_SOL,
+      data: Buffer.alloc(0),
+    });
+  }
+
+  async function migrateGlobalInfoOnSvm(
+    svm: LiteSVM,
+    signer: anchor.web3.Keypair
+  ) {
+    const instruction = await setup.program.methods
+      .migrateGlobalInfo()
+      .accounts({
+        signer: signer.publicKey,
+        programData: setup.programDataPDA,
+      })
+      .instruction();
+    const tx = new anchor.web3.Transaction().add(instruction);
+    tx.recentBlockhash = svm.latestBlockhash();
+    tx.feePayer = signer.publicKey;
+    tx.sign(signer);
+    return svm.sendTransaction(tx);
+  }
+
+  function expectSvmError(result: unknown, code: string) {
+    assert(
+      result instanceof FailedTransactionMetadata,
+      `Expected error ${code}, but transaction succeeded`
+    );
+    const logs = result.meta().logs();
+    assert(
+      logs.some((log) => log.includes(`Error Code: ${code}.`)),
+      `Expected error ${code}, got ${result.err()}`
+    );
+  }
+
+  // GlobalInfo as the baseline initialize_program wrote it
+  function legacyGlobalInfo(
+    developer: anchor.web3.PublicKey,
+    founder: anchor.web3.PublicKey
+  ) {
+    const data = Buffer.alloc(8 + 72);
+    crypto
+      .createHash("sha256")
+      .update("account:GlobalInfo")
+      .digest()
+      .copy(data, 0, 0, 8);
+    data.writeUInt8(1, 8); // is_initialized
+    developer.toBuffer().copy(data, 9);
+    founder.toBuffer().copy(data, 41);
+    data.writeUInt8(5, 73); // fee_percentage
+    data.writeUInt16LE(1000, 74); // fee_percentage_divider
+    data.writeUInt8(2, 76); // min_fee_for_distribution
+    data.writeUInt8(2, 77); // min_fee
+    data.writeUInt8(50, 78); // developer_fee_share
+    data.writeUInt8(50, 79); // founder_fee_share
+    return data;
+  }
+
+  it("Migrate Legacy Global Info", async () => {
+    const svm = new LiteSVM();
+    const upgradeAuthority = anchor.web3.Keypair.generate();
+    const hijacker = anchor.web3.Keypair.generate();
+    const developer = anchor.web3.Keypair.generate().publicKey;
+    const founder = anchor.web3.Keypair.generate().publicKey;
+    deployToSvm(svm, upgradeAuthority.publicKey);
+    fundOnSvm(svm, upgradeAuthority.publicKey);
+    fundOnSvm(svm, hijacker.publicKey);
+
+    const legacyData = legacyGlobalInfo(developer, founder);
+    svm.setAccount(setup.globalInfoPDA, {
+      executable: false,
+      owner: setup.program.programId,
+      lamports: await setup.connection.getMinimumBalanceForRentExemption(
+        legacyData.length
+      ),
+      data: legacyData,
+    });
+
+    // the version byte picks the layout, the length has to agree with it
+    const unknownVersion = Buffer.from(legacyData);
+    unknownVersion.writeUInt8(3, 8);
+    const truncated = legacyData.subarray(0, legacyData.length - 1);
+    for (const data of [unknownVersion, truncated]) {
+      svm.setAccount(setup.globalInfoPDA, {
+        ...svm.getAccount(setup.globalInfoPDA),
+        data,
+      });
+      const failed = await migrateGlobalInfoOnSvm(svm, upgradeAuthority);
+      assert(
+        failed instanceof FailedTransactionMetadata &&
+          failed
+            .meta()
+            .logs()
+            .some((log) => log.includes("invalid account data")),
+        "Unexpected Layout Migrated"
+      );
+    }
+    svm.setAccount(setup.globalInfoPDA, {
+      ...svm.getAccount(setup.globalInfoPDA),
+      data: legacyData,
+    });
+
+    // the legacy layout has no admin, only the upgrade authority may claim it
+    expectSvmError(await migrateGlobalInfoOnSvm(svm, hijacker), "Unauthorized");
+    assert(
+      svm.getAccount(setup.globalInfoPDA).data.length == legacyData.length,
+      "Legacy Global Info Migrated By Hijacker"
+    );
+
+    const result = await migrateGlobalInfoOnSvm(svm, upgradeAuthority);
+    assert(
+      !(result instanceof FailedTransactionMetadata),
+      `Migration Failed: ${result.toString()}`
+    );
+
+    const globalInfo = setup.program.coder.accounts.decode(
+      "GlobalInfo",
+      Buffer.from(svm.getAccount(setup.globalInfoPDA).data)
+    );
+    assert(globalInfo.version == 2, "Wrong Global Info Version");
+    assert(globalInfo.isInitialized, "Initialized Flag Lost");
+    const [developerRecipient, founderRecipient] = globalInfo.feeRecipients;
+    assert(globalInfo.feeRecipients.length == 2, "Wrong Fee Recipient Count");
+    assert(developerRecipient.wallet.equals(developer), "Developer Lost");
+    assert(founderRecipient.wallet.equals(founder), "Founder Lost");
+    assert(developerRecipient.weightBps == 5000, "Wrong Developer Weight");
+    assert(founderRecipient.weightBps == 5000, "Wrong Founder Weight");
+    for (const feeConfig of [globalInfo.lockFee, globalInfo.unlockFee]) {
+      // 5 / 1000
+      assert(feeConfig.feeBps == 50, "Wrong feeBps");
+      assert(feeConfig.minFee.eqn(2), "Wrong minFee");
+      assert(feeConfig.maxFee.eq(U64_MAX), "Wrong maxFee");
+    }
+    assert(
+      globalInfo.admin.equals(upgradeAuthority.publicKey),
+      "Upgrade Authority Not Admin"
+    );
+    assert(
+      globalInfo.pendingAdmin.equals(anchor.web3.PublicKey.default),
+      "Pending Admin Set"
+    );
+    assert(globalInfo.paused == 0, "Migrated Paused");
+    assert(globalInfo.feeRemainderIndex == 1, "Wrong Remainder Index");
+    assert(globalInfo.referralFeeBps == 0, "Referral Fee Set");
+    assert(globalInfo.permissionlessRegistration, "Registration Restricted");
+    assert("open" in globalInfo.tokenListMode, "Token List Not Open");
+
+    expectSvmError(
+      await migrateGlobalInfoOnSvm(svm, upgradeAuthority),
+      "AccountAlreadyMigrated"
+    );
+  });
+
+  it("Migrate Legacy Token Info", async () => {
+    const { pda: tokenInfoPDA } = setup.getTokenInfoPDA(legacyMint.publicKey);
+    const legacyAccount = await setup.connection.getAccountInfo(tokenInfoPDA);
+    assert(
+      legacyAccount.data.length == legacyAccountSize,
+      "Legacy Token Info Not Loaded"
+    );
+
+    // the blobs do not carry the original mint, recreate it with the vault
+    // holding what the legacy holder locked
+    await splToken.createMint(
+      setup.connection,
+      setup.payer,
+      setup.payer.publicKey,
+      null,
+      decimals,
+      legacyMint
+    );
+    const vault = setup.getTokenVault(legacyMint.publicKey);
+    await splToken.createAssociatedTokenAccountIdempotent(
+      setup.connection,
+      setup.payer,
+      legacyMint.publicKey,
+      vault.authority,
+      {},
+      splToken.TOKEN_PROGRAM_ID,
+      splToken.ASSOCIATED_TOKEN_PROGRAM_ID,
+      true
+    );
+    await splToken.mintTo(
+      setup.connection,
+      setup.payer,
+      legacyMint.publicKey,
+      vault.ata,
+      setup.payer,
+      legacySupply
+    );
+
+    // anyone may migrate, the upgrade cannot change what the account says
+    const migratedAfter = Math.floor(Date.now() / 1000) - 60;
+    await migrateTokenInfo(legacyMint.publicKey, setup.user);
+
+    const tokenInfo = await setup.program.account.tokenInfo.fetch(tokenInfoPDA);
+    const derivativeMint = setup.getDerivativeMint(legacyMint.publicKey).pda;
+    assert(tokenInfo.version == 2, "Wrong Token Info Version");
+    assert(tokenInfo.isInitialized, "Initialized Flag Lost");
+    assert(
+      tokenInfo.originalMint.equals(legacyMint.publicKey),
+      "Original Mint Lost"
+    );
+    assert(
+      tokenInfo.derivativeMint.equals(derivativeMint),
+      "Derivative Mint Lost"
+    );
+    assert(tokenInfo.lockFeeOverride === null, "Lock Fee Override Set");
+    assert(tokenInfo.unlockFeeOverride === null, "Unlock Fee Override Set");
+    assert(tokenInfo.totalLocked.eqn(legacySupply), "Wrong Locked Total");
+    assert(tokenInfo.totalUnlocked.eqn(0), "Wrong Unlocked Total");
+    assert(
+      tokenInfo.createdAt.toNumber() >= migratedAfter,
+      "Creation Time Not Set"
+    );
+
+    const feeVault = await setup.program.account.feeVault.fetch(
+      setup.getFeeVault(legacyMint.publicKey).pda
+    );
+    assert(
+      feeVault.originalMint.equals(legacyMint.publicKey),
+      "Fee Vault Not Created"
+    );
+
+    await expectTwosideError(
+      migrateTokenInfo(legacyMint.publicKey),
+      "AccountAlreadyMigrated"
+    );
+
+    // derivatives minted before the upgrade redeem, and the token locks again
+    await setup.airdropToWallet(
+      legacyHolder.publicKey,
+      anchor.web3.LAMPORTS_PER_SOL
+    );
+    const holderTokenAta = (
+      await setup.getTokenATA(legacyMint.publicKey, legacyHolder.publicKey)
+    ).address;
+    const holderDerivativeAta = setup.getDerivativeATA(
+      derivativeMint,
+      legacyHolder.publicKey
+    );
+    // the invariant tests/backing.ts checks holds across the upgrade
+    const assertBooksMatchVault = async (step: string) => {
+      const books = await setup.program.account.tokenInfo.fetch(tokenInfoPDA);
+      assert(
+        books.totalUnlocked.lte(books.totalLocked),
+        `More Unlocked Than Locked After ${step}`
+      );
+      assert(
+        books.totalLocked.sub(books.totalUnlocked).toString() ==
+          (await tokenBalance(vault.ata)).toString(),
+        `Books Do Not Match Vault After ${step}`
+      );
+    };
+
+    await setup.program.methods
+      .unlock(new anchor.BN(legacySupply))
+      .accounts(
+        await setup.tokenOpAccounts(
+          legacyMint.publicKey,
+          legacyHolder.publicKey
+        )
+      )
+      .signers([legacyHolder])
+      .rpc();
+    const unlocked = legacySupply - setup.calculateFee(legacySupply);
+    assert(
+      (await tokenBalance(holderTokenAta)) == BigInt(unlocked),
+      "Legacy Derivatives Not Redeemed"
+    );
+    assert(
+      (await tokenBalance(holderDerivativeAta)) == BigInt(0),
+      "Legacy Derivatives Not Burned"
+    );
+    await assertBooksMatchVault("Unlock");
+
+    await setup.program.methods
+      .lock(new anchor.BN(unlocked))
+      .accounts(
+        await setup.tokenOpAccounts(
+          legacyMint.publicKey,
+          legacyHolder.publicKey
+        )
+      )
+      .signers([legacyHolder])
+      .rpc();
+    assert(
+      (await tokenBalance(holderDerivativeAta)) ==
+        BigInt(unlocked - setup.calculateFee(unlocked)),
+      "Migrated Token Not Locked"
+    );
+    await assertBooksMatchVault("Lock");
+  });
+
+  it("Migrate Current Accounts", async () => {
+    const tokenMint = await setup.createTestToken(decimals, 0);
+    await setup.registerToken(tokenMint);
+    await expectTwosideError(
+      migrateTokenInfo(tokenMint),
+      "AccountAlreadyMigrated"
+    );
+
+    const globalInfo = await setup.program.account.globalInfo.fetch(
+      setup.globalInfoPDA
+    );
+    assert(globalInfo.version == 2, "Wrong Global Info Version");
+    await expectTwosideError(
+      setup.program.methods
+        .migrateGlobalInfo()
+        .accounts({
+          signer: setup.admin.publicKey,
+          programData: setup.programDataPDA,
+        })
+        .signers([setup.admin])
+        .rpc(),
+      "AccountAlreadyMigrated"
+    );
+  });
+});
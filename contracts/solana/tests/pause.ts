@@ -0,0 +1,125 @@
+import { assert } from "chai";
+import * as anchor from "@coral-xyz/anchor";
+import { expectTwosideError, setup } from "./setup";
+import { tokenDecimals } from "./lock";
+
+const PAUSE_LOCK = 1;
+const PAUSE_UNLOCK = 2;
+
+describe("Pausing", () => {
+  const amount = new anchor.BN(10 ** tokenDecimals);
+  let tokenMint: anchor.web3.PublicKey;
+
+  before(async () => {
+    tokenMint = await setup.createTestToken(
+      tokenDecimals,
+      100 * 10 ** tokenDecimals
+    );
+    await setup.registerToken(tokenMint);
+    await setup.program.methods
+      .lock(amount.muln(10))
+      .accounts(await setup.tokenOpAccounts(tokenMint, setup.user.publicKey))
+      .signers([setup.user])
+      .rpc();
+  });
+
+  async function setGlobalPause(paused: number) {
+    await setup.program.methods
+      .setGlobalPause(paused)
+      .accounts({
+        admin: setup.admin.publicKey,
+      })
+      .signers([setup.admin])
+      .rpc();
+  }
+
+  async function setTokenPause(paused: number) {
+    await setup.program.methods
+      .setTokenPause(paused)
+      .accounts({
+        admin: setup.admin.publicKey,
+        tokenInfo: setup.getTokenInfoPDA(tokenMint).pda,
+      })
+      .signers([setup.admin])
+      .rpc();
+  }
+
+  it("Global Lock Pause", async () => {
+    await setGlobalPause(PAUSE_LOCK);
+
+    const globalInfo = await setup.program.account.globalInfo.fetch(
+      setup.globalInfoPDA
+    );
+    assert(globalInfo.paused == PAUSE_LOCK, "Wrong Global Pause Set");
+
+    await expectTwosideError(
+      setup.program.methods
+        .lock(amount)
+        .accounts(await setup.tokenOpAccounts(tokenMint, setup.user.publicKey))
+        .signers([setup.user])
+        .rpc(),
+      "Paused"
+    );
+
+    // unlocks stay open while only locking is paused
+    await setup.program.methods
+      .unlock(amount)
+      .accounts(await setup.tokenOpAccounts(tokenMint, setup.user.publicKey))
+      .signers([setup.user])
+      .rpc();
+
+    await setGlobalPause(0);
+  });
+
+  it("Token Unlock Pause", async () => {
+    await setTokenPause(PAUSE_UNLOCK);
+
+    const tokenInfo = await setup.program.account.tokenInfo.fetch(
+      setup.getTokenInfoPDA(tokenMint).pda
+    );
+    assert(tokenInfo.paused == PAUSE_UNLOCK, "Wrong Token Pause Set");
+
+    await expectTwosideError(
+      setup.program.methods
+        .unlock(amount)
+        .accounts(await setup.tokenOpAccounts(tokenMint, setup.user.publicKey))
+        .signers([setup.user])
+        .rpc(),
+      "Paused"
+    );
+
+    await setup.program.methods
+      .lock(amount)
+      .accounts(await setup.tokenOpAccounts(tokenMint, setup.user.publicKey))
+      .signers([setup.user])
+      .rpc();
+
+    await setTokenPause(0);
+  });
+
+  it("Invalid Pause Flags", async () => {
+    await expectTwosideError(
+      setup.program.methods
+        .setGlobalPause(4)
+        .accounts({
+          admin: setup.admin.publicKey,
+        })
+        .signers([setup.admin])
+        .rpc(),
+      "InvalidPauseFlags"
+    );
+  });
+
+  it("Non-Admin Pause", async () => {
+    await expectTwosideError(
+      setup.program.methods
+        .setGlobalPause(PAUSE_LOCK)
+        .accounts({
+          admin: setup.payer.publicKey,
+        })
+        .signers([setup.payer])
+        .rpc(),
+      "Unauthorized"
+    );
+  });
+});
@@ -0,0 +1,66 @@
+import { assert } from "chai";
+import * as anchor from "@coral-xyz/anchor";
+import { expectTwosideError, setup } from "./setup";
+
+describe("Token Registration", () => {
+  const decimals = 9;
+  const lockAmount = 10 * 10 ** decimals;
+
+  async function setPermissionlessRegistration(enabled: boolean) {
+    await setup.program.methods
+      .updateGlobalConfig({
+        ...setup.defaultGlobalConfig(),
+        permissionlessRegistration: enabled,
+      })
+      .accounts({
+        admin: setup.admin.publicKey,
+      })
+      .signers([setup.admin])
+      .rpc();
+  }
+
+  it("Permissioned Registration", async () => {
+    const tokenMint = await setup.createTestToken(decimals, lockAmount);
+
+    await setPermissionlessRegistration(false);
+    try {
+      await expectTwosideError(setup.registerToken(tokenMint), "Unauthorized");
+
+      await setup.registerToken(tokenMint, undefined, undefined, setup.admin);
+    } finally {
+      await setPermissionlessRegistration(true);
+    }
+
+    const tokenInfo = await setup.program.account.tokenInfo.fetch(
+      setup.getTokenInfoPDA(tokenMint).pda
+    );
+    assert(
+      tokenInfo.derivativeMint.toString() ==
+        setup.getDerivativeMint(tokenMint).pda.toString(),
+      "Wrong Derivative Mint Set"
+    );
+    assert(tokenInfo.version == 2, "Wrong Token Info Version");
+    assert(tokenInfo.createdAt.gtn(0), "Registration Time Not Set");
+    assert(tokenInfo.totalLocked.eqn(0), "Fresh Token Has Locked Total");
+
+    // anyone can lock once the admin registered the token
+    await setup.program.methods
+      .lock(new anchor.BN(lockAmount))
+      .accounts(await setup.tokenOpAccounts(tokenMint, setup.user.publicKey))
+      .signers([setup.user])
+      .rpc();
+  });
+
+  it("Lock Unregistered Token", async () => {
+    const tokenMint = await setup.createTestToken(decimals, lockAmount);
+
+    await expectTwosideError(
+      setup.program.methods
+        .lock(new anchor.BN(lockAmount))
+        .accounts(await setup.tokenOpAccounts(tokenMint, setup.user.publicKey))
+        .signers([setup.user])
+        .rpc(),
+      "AccountNotInitialized"
+    );
+  });
+});
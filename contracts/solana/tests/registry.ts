@@ -0,0 +1,95 @@
+import * as anchor from "@coral-xyz/anchor";
+import { expectTwosideError, setup } from "./setup";
+
+describe("Token Registry", () => {
+  const decimals = 9;
+  const lockAmount = 10 * 10 ** decimals;
+
+  async function setTokenListMode(mode: object) {
+    await setup.program.methods
+      .updateGlobalConfig({
+        ...setup.defaultGlobalConfig(),
+        tokenListMode: mode,
+      })
+      .accounts({
+        admin: setup.admin.publicKey,
+      })
+      .signers([setup.admin])
+      .rpc();
+  }
+
+  async function setTokenListStatus(
+    tokenMint: anchor.web3.PublicKey,
+    status: object
+  ) {
+    await setup.program.methods
+      .setTokenListStatus(status)
+      .accounts({
+        admin: setup.admin.publicKey,
+        tokenMint: tokenMint,
+      })
+      .signers([setup.admin])
+      .rpc();
+  }
+
+  async function lock(tokenMint: anchor.web3.PublicKey) {
+    return await setup.program.methods
+      .lock(new anchor.BN(lockAmount))
+      .accounts(await setup.tokenOpAccounts(tokenMint, setup.user.publicKey))
+      .signers([setup.user])
+      .rpc();
+  }
+
+  afterEach(async () => {
+    await setTokenListMode({ open: {} });
+  });
+
+  it("Allowlist Mode", async () => {
+    const tokenMint = await setup.createTestToken(decimals, 2 * lockAmount);
+    await setTokenListMode({ allowlist: {} });
+
+    await expectTwosideError(setup.registerToken(tokenMint), "TokenNotAllowed");
+
+    await setTokenListStatus(tokenMint, { allowed: {} });
+    await setup.registerToken(tokenMint);
+    await lock(tokenMint);
+
+    // delisting stops new locks of an already registered token
+    await setTokenListStatus(tokenMint, { unlisted: {} });
+    await expectTwosideError(lock(tokenMint), "TokenNotAllowed");
+  });
+
+  it("Denylist Mode", async () => {
+    const tokenMint = await setup.createTestToken(decimals, 2 * lockAmount);
+    await setTokenListMode({ denylist: {} });
+
+    await setup.registerToken(tokenMint);
+    await lock(tokenMint);
+
+    await setTokenListStatus(tokenMint, { denied: {} });
+    await expectTwosideError(lock(tokenMint), "TokenNotAllowed");
+
+    // unlocks stay open for denied tokens
+    await setup.program.methods
+      .unlock(new anchor.BN(lockAmount / 2))
+      .accounts(await setup.tokenOpAccounts(tokenMint, setup.user.publicKey))
+      .signers([setup.user])
+      .rpc();
+  });
+
+  it("Non-Admin Token List Update", async () => {
+    const tokenMint = await setup.createTestToken(decimals, lockAmount);
+
+    await expectTwosideError(
+      setup.program.methods
+        .setTokenListStatus({ denied: {} })
+        .accounts({
+          admin: setup.payer.publicKey,
+          tokenMint: tokenMint,
+        })
+        .signers([setup.payer])
+        .rpc(),
+      "Unauthorized"
+    );
+  });
+});
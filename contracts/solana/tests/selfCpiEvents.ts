@@ -0,0 +1,47 @@
+import { assert } from "chai";
+import * as anchor from "@coral-xyz/anchor";
+import { setup } from "./setup";
+
+// Only runs against the event-cpi build, see `yarn test:event-cpi`
+describe("Self-CPI Events", () => {
+  const decimals = 6;
+  const lockAmount = 1_000 * 10 ** decimals;
+
+  before(function () {
+    const lock = setup.program.idl.instructions.find((ix) => ix.name == "lock");
+    if (!lock.accounts.some((account) => account.name == "eventAuthority")) {
+      this.skip();
+    }
+  });
+
+  it("Lock Event From Inner Instruction", async () => {
+    const tokenMint = await setup.createTestToken(decimals, lockAmount);
+    await setup.registerToken(tokenMint);
+
+    const signature = await setup.program.methods
+      .lock(new anchor.BN(lockAmount))
+      .accounts(await setup.tokenOpAccounts(tokenMint, setup.user.publicKey))
+      .signers([setup.user])
+      .rpc({ commitment: "confirmed" });
+
+    const tx = await setup.connection.getTransaction(signature, {
+      commitment: "confirmed",
+      maxSupportedTransactionVersion: 0,
+    });
+    assert(
+      !tx.meta.logMessages.some((log) => log.startsWith("Program data:")),
+      "Event Logged In Event-CPI Build"
+    );
+
+    const locked = (await setup.getEvents(signature)).find(
+      (event) => event.name == "assetsLockedV2"
+    );
+    assert(locked, "Event Missing From Inner Instructions");
+    const fee = setup.calculateFee(lockAmount);
+    assert(locked.data.account.equals(setup.user.publicKey), "Wrong Account");
+    assert(locked.data.token.equals(tokenMint), "Wrong Token");
+    assert(locked.data.amount.eqn(lockAmount), "Wrong Amount");
+    assert(locked.data.fee.eqn(fee), "Wrong Fee");
+    assert(locked.data.netAmount.eqn(lockAmount - fee), "Wrong Net Amount");
+  });
+});
@@ -1,6 +1,7 @@
 import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import type { Twoside } from "../target/types/twoside";
+import type { CpiForwarder } from "../target/types/cpi_forwarder";
 import * as splToken from "@solana/spl-token";
 import { createUmi } from "@metaplex-foundation/umi-bundle-defaults";
 import {
@@ -25,6 +26,8 @@ import {
 } from "@metaplex-foundation/mpl-token-metadata";
 import { Connection, PublicKey } from "@solana/web3.js";
 
+export const U64_MAX = new anchor.BN("18446744073709551615");
+
 class Setup {
   private static instance: Setup | null = null;
 
@@ -40,6 +43,20 @@ class Setup {
   );
   public DERIVATIVE_MINT_STATIC_SEED: Buffer<ArrayBuffer> =
     Buffer.from("derivative_mint");
+  public FEE_VAULT_STATIC_SEED: Buffer<ArrayBuffer> = Buffer.from("fee_vault");
+  public REFERRER_STATS_STATIC_SEED: Buffer<ArrayBuffer> =
+    Buffer.from("referrer_stats");
+  public REFERRER_ENTRY_STATIC_SEED: Buffer<ArrayBuffer> =
+    Buffer.from("referrer_entry");
+  public USER_VOLUME_STATIC_SEED: Buffer<ArrayBuffer> =
+    Buffer.from("user_volume");
+  public FEE_EXEMPTION_STATIC_SEED: Buffer<ArrayBuffer> =
+    Buffer.from("fee_exemption");
+  // anchor_lang::event::EVENT_IX_TAG, little endian
+  public EVENT_IX_TAG: Buffer<ArrayBuffer> = Buffer.from(
+    "e445a52e51cb9a1d",
+    "hex"
+  );
 
   public provider: anchor.AnchorProvider;
   public connection: anchor.web3.Connection;
@@ -47,11 +64,16 @@ class Setup {
   public umiSigner: KeypairSigner;
 
   public program: anchor.Program<Twoside>;
+  public programDataPDA: anchor.web3.PublicKey;
+  // Relays instructions to reach the program through CPI
+  public forwarder: anchor.Program<CpiForwarder>;
+
+  // Program upgrade authority, which is also the initial admin
+  public admin: anchor.web3.Keypair;
 
   public developer: anchor.web3.Keypair;
   public founder: anchor.web3.Keypair;
-  public fee_percentage: number;
-  public fee_percentage_divider: number;
+  public fee_bps: number;
 
   public user: anchor.web3.Keypair;
   public payer: anchor.web3.Keypair;
@@ -77,11 +99,16 @@ class Setup {
     this.connection = this.provider.connection;
 
     this.program = anchor.workspace.Twoside as Program<Twoside>;
+    this.forwarder = anchor.workspace.CpiForwarder as Program<CpiForwarder>;
+    this.admin = (this.provider.wallet as anchor.Wallet).payer;
+    [this.programDataPDA] = anchor.web3.PublicKey.findProgramAddressSync(
+      [this.program.programId.toBuffer()],
+      new PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+    );
 
     this.developer = anchor.web3.Keypair.generate();
     this.founder = anchor.web3.Keypair.generate();
-    this.fee_percentage = 5;
-    this.fee_percentage_divider = 1000;
+    this.fee_bps = 50;
 
     this.user = anchor.web3.Keypair.generate();
     this.payer = anchor.web3.Keypair.generate();
@@ -126,6 +153,106 @@ class Setup {
     return (this.tokenMint = mint);
   }
 
+  // Fresh mint with metadata and a funded owner ATA, without touching tokenMint
+  public async createTestToken(
+    decimals: number,
+    balance: number | bigint,
+    owner: anchor.web3.PublicKey = this.user.publicKey,
+    withMetadata: boolean = true
+  ): Promise<anchor.web3.PublicKey> {
+    const mint = await splToken.createMint(
+      this.connection,
+      this.payer,
+      this.payer.publicKey,
+      this.payer.publicKey,
+      decimals
+    );
+    await this.fundTestToken(
+      mint,
+      balance,
+      owner,
+      splToken.TOKEN_PROGRAM_ID,
+      withMetadata
+    );
+    return mint;
+  }
+
+  // Token-2022 mint whose extensions are initialized by extensionInstructions
+  public async createToken2022TestToken(
+    decimals: number,
+    balance: number | bigint,
+    extensions: splToken.ExtensionType[],
+    extensionInstructions: (
+      mint: anchor.web3.PublicKey
+    ) => anchor.web3.TransactionInstruction[],
+    owner: anchor.web3.PublicKey = this.user.publicKey
+  ): Promise<anchor.web3.PublicKey> {
+    const mintKeypair = anchor.web3.Keypair.generate();
+    const mint = mintKeypair.publicKey;
+    const mintLen = splToken.getMintLen(extensions);
+    const lamports =
+      await this.connection.getMinimumBalanceForRentExemption(mintLen);
+
+    const tx = new anchor.web3.Transaction().add(
+      anchor.web3.SystemProgram.createAccount({
+        fromPubkey: this.payer.publicKey,
+        newAccountPubkey: mint,
+        space: mintLen,
+        lamports,
+        programId: splToken.TOKEN_2022_PROGRAM_ID,
+      }),
+      ...extensionInstructions(mint),
+      splToken.createInitializeMintInstruction(
+        mint,
+        decimals,
+        this.payer.publicKey,
+        this.payer.publicKey,
+        splToken.TOKEN_2022_PROGRAM_ID
+      )
+    );
+    await anchor.web3.sendAndConfirmTransaction(this.connection, tx, [
+      this.payer,
+      mintKeypair,
+    ]);
+
+    await this.fundTestToken(
+      mint,
+      balance,
+      owner,
+      splToken.TOKEN_2022_PROGRAM_ID
+    );
+    return mint;
+  }
+
+  public async fundTestToken(
+    mint: anchor.web3.PublicKey,
+    balance: number | bigint,
+    owner: anchor.web3.PublicKey,
+    tokenProgram: anchor.web3.PublicKey = splToken.TOKEN_PROGRAM_ID,
+    withMetadata: boolean = true
+  ) {
+    if (withMetadata) {
+      await this.deployMetaplexMetadata(
+        "TestToken",
+        "TT",
+        "https://example.com/metadata.json",
+        mint
+      );
+    }
+    const ownerAta = await this.getTokenATA(mint, owner, tokenProgram);
+    await splToken.mintTo(
+      this.connection,
+      this.payer,
+      mint,
+      ownerAta.address,
+      this.payer.publicKey,
+      balance,
+      [],
+      undefined,
+      tokenProgram
+    );
+  }
+
   public getTokenMetadataPDA(mint: anchor.web3.PublicKey): {
     pda: anchor.web3.PublicKey;
     bump: number;
@@ -190,26 +317,109 @@ class Setup {
     };
   }
 
+  public getFeeVault(
+    mint: anchor.web3.PublicKey,
+    tokenProgram: anchor.web3.PublicKey = splToken.TOKEN_PROGRAM_ID
+  ): {
+    pda: anchor.web3.PublicKey;
+    bump: number;
+    ata: anchor.web3.PublicKey;
+  } {
+    const [feeVaultPDA, feeVaultBump] =
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [this.FEE_VAULT_STATIC_SEED, mint.toBuffer()],
+        this.program.programId
+      );
+
+    return {
+      pda: feeVaultPDA,
+      bump: feeVaultBump,
+      ata: splToken.getAssociatedTokenAddressSync(
+        mint,
+        feeVaultPDA,
+        true,
+        tokenProgram
+      ),
+    };
+  }
+
+  public getReferrerStatsPDA(
+    referrer: anchor.web3.PublicKey,
+    mint: anchor.web3.PublicKey
+  ): {
+    pda: anchor.web3.PublicKey;
+    bump: number;
+  } {
+    const [referrerStatsPDA, referrerStatsBump] =
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [this.REFERRER_STATS_STATIC_SEED, referrer.toBuffer(), mint.toBuffer()],
+        this.program.programId
+      );
+    return {
+      pda: referrerStatsPDA,
+      bump: referrerStatsBump,
+    };
+  }
+
+  public getReferrerEntryPDA(referrer: anchor.web3.PublicKey): {
+    pda: anchor.web3.PublicKey;
+    bump: number;
+  } {
+    const [referrerEntryPDA, referrerEntryBump] =
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [this.REFERRER_ENTRY_STATIC_SEED, referrer.toBuffer()],
+        this.program.programId
+      );
+    return {
+      pda: referrerEntryPDA,
+      bump: referrerEntryBump,
+    };
+  }
+
+  public getUserVolumePDA(
+    owner: anchor.web3.PublicKey,
+    mint: anchor.web3.PublicKey
+  ): {
+    pda: anchor.web3.PublicKey;
+    bump: number;
+  } {
+    const [userVolumePDA, userVolumeBump] =
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [this.USER_VOLUME_STATIC_SEED, owner.toBuffer(), mint.toBuffer()],
+        this.program.programId
+      );
+    return {
+      pda: userVolumePDA,
+      bump: userVolumeBump,
+    };
+  }
+
   public async getTokenATA(
     mint: anchor.web3.PublicKey,
-    owner: anchor.web3.PublicKey
+    owner: anchor.web3.PublicKey,
+    tokenProgram: anchor.web3.PublicKey = splToken.TOKEN_PROGRAM_ID
   ) {
     return await splToken.getOrCreateAssociatedTokenAccount(
       this.connection,
       this.payer,
       mint,
-      owner
+      owner,
+      false,
+      undefined,
+      undefined,
+      tokenProgram
     );
   }
 
   public getDerivativeATA(
     derivativeMint: anchor.web3.PublicKey,
-    owner: anchor.web3.PublicKey
+    owner: anchor.web3.PublicKey,
+    tokenProgram: anchor.web3.PublicKey = splToken.TOKEN_PROGRAM_ID
   ): anchor.web3.PublicKey {
     const [derivativeAta] = PublicKey.findProgramAddressSync(
       [
         owner.toBuffer(),
-        splToken.TOKEN_PROGRAM_ID.toBuffer(),
+        tokenProgram.toBuffer(),
         derivativeMint.toBuffer(),
       ],
       splToken.ASSOCIATED_TOKEN_PROGRAM_ID
@@ -217,7 +427,10 @@ class Setup {
     return derivativeAta;
   }
 
-  public getTokenVault(mint: anchor.web3.PublicKey): {
+  public getTokenVault(
+    mint: anchor.web3.PublicKey,
+    tokenProgram: anchor.web3.PublicKey = splToken.TOKEN_PROGRAM_ID
+  ): {
     authority: anchor.web3.PublicKey;
     authorityBump: number;
     ata: anchor.web3.PublicKey;
@@ -232,7 +445,7 @@ class Setup {
     const [vaultAta, vaultAtaBump] = PublicKey.findProgramAddressSync(
       [
         vaultAuthorityPDA.toBuffer(),
-        splToken.TOKEN_PROGRAM_ID.toBuffer(),
+        tokenProgram.toBuffer(),
         mint.toBuffer(),
       ],
       splToken.ASSOCIATED_TOKEN_PROGRAM_ID
@@ -278,14 +491,167 @@ class Setup {
     };
   }
 
+  public registerAccounts(
+    mint: anchor.web3.PublicKey,
+    signer: anchor.web3.PublicKey,
+    tokenProgram: anchor.web3.PublicKey = splToken.TOKEN_PROGRAM_ID,
+    derivativeTokenProgram: anchor.web3.PublicKey = tokenProgram
+  ) {
+    const { pda: derivativeMint } = this.getDerivativeMint(mint);
+    return {
+      tokenProgram: tokenProgram,
+      derivativeTokenProgram: derivativeTokenProgram,
+      tokenMint: mint,
+      tokenMetadata: this.getTokenMetadataPDA(mint).pda,
+      derivativeMetadata: this.getTokenMetadataPDA(derivativeMint).pda,
+      signer: signer,
+      mplTokenMetadataProgram: MPL_TOKEN_METADATA_PROGRAM_ID,
+    };
+  }
+
+  public async registerToken(
+    mint: anchor.web3.PublicKey,
+    tokenProgram: anchor.web3.PublicKey = splToken.TOKEN_PROGRAM_ID,
+    derivativeTokenProgram: anchor.web3.PublicKey = tokenProgram,
+    signer: anchor.web3.Keypair = this.user
+  ) {
+    return await this.program.methods
+      .registerToken()
+      .accounts(
+        this.registerAccounts(
+          mint,
+          signer.publicKey,
+          tokenProgram,
+          derivativeTokenProgram
+        )
+      )
+      .signers([signer])
+      .rpc();
+  }
+
+  // Accounts shared by lock and unlock, without the optional fee extras
+  public async tokenOpAccounts(
+    mint: anchor.web3.PublicKey,
+    signer: anchor.web3.PublicKey,
+    tokenProgram: anchor.web3.PublicKey = splToken.TOKEN_PROGRAM_ID,
+    derivativeTokenProgram: anchor.web3.PublicKey = tokenProgram
+  ) {
+    return {
+      tokenProgram: tokenProgram,
+      derivativeTokenProgram: derivativeTokenProgram,
+      tokenMint: mint,
+      signer: signer,
+      signerTokenAta: (await this.getTokenATA(mint, signer, tokenProgram))
+        .address,
+      callerFeeExemption: null,
+      tokenPriceFeed: null,
+      solPriceFeed: null,
+      referrer: null,
+      referrerTokenAccount: null,
+      referrerStats: null,
+      referrerEntry: null,
+      userVolume: null,
+    };
+  }
+
+  // Events the program emitted in a confirmed transaction, logged by the
+  // default build or carried by self-CPI inner instructions with event-cpi
+  public async getEvents(signature: string) {
+    const tx = await this.connection.getTransaction(signature, {
+      commitment: "confirmed",
+      maxSupportedTransactionVersion: 0,
+    });
+    const parser = new anchor.EventParser(
+      this.program.programId,
+      this.program.coder
+    );
+    const events = Array.from(parser.parseLogs(tx.meta.logMessages));
+
+    const accountKeys = tx.transaction.message.getAccountKeys({
+      accountKeysFromLookups: tx.meta.loadedAddresses,
+    });
+    for (const inner of tx.meta.innerInstructions ?? []) {
+      for (const ix of inner.instructions) {
+        const data = Buffer.from(anchor.utils.bytes.bs58.decode(ix.data));
+        if (
+          accountKeys.get(ix.programIdIndex).equals(this.program.programId) &&
+          data.subarray(0, 8).equals(this.EVENT_IX_TAG)
+        ) {
+          const event = this.program.coder.events.decode(
+            data.subarray(8).toString("base64")
+          );
+          if (event) {
+            events.push(event);
+          }
+        }
+      }
+    }
+    return events;
+  }
+
+  public async feeBalance(
+    mint: anchor.web3.PublicKey,
+    wallet: anchor.web3.PublicKey
+  ): Promise<bigint> {
+    const feeVault = await this.program.account.feeVault.fetch(
+      this.getFeeVault(mint).pda
+    );
+    const balance = feeVault.balances.find((entry) =>
+      entry.wallet.equals(wallet)
+    );
+    return BigInt(balance ? balance.amount.toString() : 0);
+  }
+
+  public async claimFees(
+    mint: anchor.web3.PublicKey,
+    recipient: anchor.web3.PublicKey,
+    tokenProgram: anchor.web3.PublicKey = splToken.TOKEN_PROGRAM_ID,
+    signer: anchor.web3.Keypair = this.payer
+  ) {
+    return await this.program.methods
+      .claimFees()
+      .accounts({
+        tokenProgram: tokenProgram,
+        tokenMint: mint,
+        signer: signer.publicKey,
+        recipient: recipient,
+      })
+      .signers([signer])
+      .rpc();
+  }
+
   public calculateFee(amount: number): number {
-    return (amount * this.fee_percentage) / this.fee_percentage_divider;
+    return Math.floor((amount * this.fee_bps + 5_000) / 10_000);
   }
 
   public feeShare(fee: number): number {
     return fee / 2;
   }
 
+  public defaultFeeConfig() {
+    return {
+      feeBps: this.fee_bps,
+      minFee: new anchor.BN(0),
+      maxFee: U64_MAX,
+    };
+  }
+
+  public defaultGlobalConfig() {
+    return {
+      feeRecipients: [
+        { wallet: this.developer.publicKey, weightBps: 5_000 },
+        { wallet: this.founder.publicKey, weightBps: 5_000 },
+      ],
+      lockFee: this.defaultFeeConfig(),
+      unlockFee: this.defaultFeeConfig(),
+      derivativeMetadataMode: { metaplex: {} },
+      permissionlessRegistration: true,
+      tokenListMode: { open: {} },
+      feeRemainderIndex: 1,
+      referralFeeBps: 0,
+    };
+  }
+
   public getDerivativeName(tokenName: string): string {
     return "Liquid " + tokenName;
   }
@@ -308,3 +674,19 @@ export async function fetchLogsFromSignature(
   }
   return null;
 }
+
+export async function expectTwosideError(
+  promise: Promise<unknown>,
+  code: string
+) {
+  try {
+    await promise;
+  } catch (err: any) {
+    const errorCode = err?.error?.errorCode?.code;
+    if (errorCode !== code) {
+      throw new Error(`Expected error ${code}, got ${errorCode ?? err}`);
+    }
+    return;
+  }
+  throw new Error(`Expected error ${code}, but transaction succeeded`);
+}
@@ -0,0 +1,400 @@
+import { assert } from "chai";
+import * as anchor from "@coral-xyz/anchor";
+import * as splToken from "@solana/spl-token";
+import { expectTwosideError, setup } from "./setup";
+
+describe("Token-2022 Locking", () => {
+  const decimals = 6;
+  const initialBalance = 1_000 * 10 ** decimals;
+  const lockAmount = 100 * 10 ** decimals;
+  const tokenProgram = splToken.TOKEN_2022_PROGRAM_ID;
+
+  it("Lock With Transfer Fee Extension", async () => {
+    const transferFeeBps = 100;
+    const tokenMint = await setup.createToken2022TestToken(
+      decimals,
+      initialBalance,
+      [splToken.ExtensionType.TransferFeeConfig],
+      (mint) => [
+        splToken.createInitializeTransferFeeConfigInstruction(
+          mint,
+          setup.payer.publicKey,
+          setup.payer.publicKey,
+          transferFeeBps,
+          BigInt(initialBalance),
+          tokenProgram
+        ),
+      ]
+    );
+
+    await setup.registerToken(tokenMint, tokenProgram);
+    await setup.program.methods
+      .lock(new anchor.BN(lockAmount))
+      .accounts(
+        await setup.tokenOpAccounts(
+          tokenMint,
+          setup.user.publicKey,
+          tokenProgram
+        )
+      )
+      .signers([setup.user])
+      .rpc();
+
+    const received = lockAmount - (lockAmount * transferFeeBps) / 10_000;
+    const minted = received - setup.calculateFee(received);
+
+    const { pda: derivativeMint } = setup.getDerivativeMint(tokenMint);
+    const derivativeMintAccount = await splToken.getMint(
+      setup.connection,
+      derivativeMint,
+      undefined,
+      tokenProgram
+    );
+    assert(
+      derivativeMintAccount.supply == BigInt(minted),
+      "Wrong Derivative Total Supply"
+    );
+
+    const { ata: vaultAta } = setup.getTokenVault(tokenMint, tokenProgram);
+    const vaultAtaAccount = await splToken.getAccount(
+      setup.connection,
+      vaultAta,
+      undefined,
+      tokenProgram
+    );
+    assert(
+      vaultAtaAccount.amount == derivativeMintAccount.supply,
+      "Vault Balance Does Not Back Derivative Supply"
+    );
+
+    // the move into the fee vault is taxed too, only what arrived is owed
+    const { ata: feeVaultAta } = setup.getFeeVault(tokenMint, tokenProgram);
+    const feeVaultAtaAccount = await splToken.getAccount(
+      setup.connection,
+      feeVaultAta,
+      undefined,
+      tokenProgram
+    );
+    assert(
+      feeVaultAtaAccount.amount ==
+        (await setup.feeBalance(tokenMint, setup.developer.publicKey)) +
+          (await setup.feeBalance(tokenMint, setup.founder.publicKey)),
+      "Fee Vault Does Not Match Fee Balances"
+    );
+
+    await setup.program.methods
+      .unlock(new anchor.BN(minted))
+      .accounts(
+        await setup.tokenOpAccounts(
+          tokenMint,
+          setup.user.publicKey,
+          tokenProgram
+        )
+      )
+      .signers([setup.user])
+      .rpc();
+
+    const derivativeMintAfter = await splToken.getMint(
+      setup.connection,
+      derivativeMint,
+      undefined,
+      tokenProgram
+    );
+    assert(
+      derivativeMintAfter.supply == BigInt(0),
+      "Derivative Supply Not Burned"
+    );
+  });
+
+  it("Lock Into Funded Vault With Transfer Fee Extension", async () => {
+    const transferFeeBps = 100;
+    const tokenMint = await setup.createToken2022TestToken(
+      decimals,
+      initialBalance,
+      [splToken.ExtensionType.TransferFeeConfig],
+      (mint) => [
+        splToken.createInitializeTransferFeeConfigInstruction(
+          mint,
+          setup.payer.publicKey,
+          setup.payer.publicKey,
+          transferFeeBps,
+          BigInt(initialBalance),
+          tokenProgram
+        ),
+      ]
+    );
+    await setup.registerToken(tokenMint, tokenProgram);
+
+    const { ata: vaultAta } = setup.getTokenVault(tokenMint, tokenProgram);
+    const { pda: derivativeMint } = setup.getDerivativeMint(tokenMint);
+    const vaultBalance = async () =>
+      (
+        await splToken.getAccount(
+          setup.connection,
+          vaultAta,
+          undefined,
+          tokenProgram
+        )
+      ).amount;
+    const derivativeSupply = async () =>
+      (
+        await splToken.getMint(
+          setup.connection,
+          derivativeMint,
+          undefined,
+          tokenProgram
+        )
+      ).supply;
+    const lock = async () =>
+      setup.program.methods
+        .lock(new anchor.BN(lockAmount))
+        .accounts(
+          await setup.tokenOpAccounts(
+            tokenMint,
+            setup.user.publicKey,
+            tokenProgram
+          )
+        )
+        .signers([setup.user])
+        .rpc({ commitment: "confirmed" });
+
+    // the second lock only backs its own delta, not the balance already held
+    await lock();
+    const vaultBefore = await vaultBalance();
+    const supplyBefore = await derivativeSupply();
+    const signature = await lock();
+    const locked = (await setup.getEvents(signature)).find(
+      (event) => event.name == "assetsLockedV2"
+    ).data;
+
+    const received = lockAmount - (lockAmount * transferFeeBps) / 10_000;
+    const fee = setup.calculateFee(received);
+    assert(locked.amount.eqn(received), "Vault Delta Not Net Of Transfer Fee");
+    assert(locked.fee.eqn(fee), "Wrong Fee On Vault Delta");
+    assert(
+      (await derivativeSupply()) - supplyBefore == BigInt(received - fee),
+      "Minted Amount Is Not Vault Delta Minus Fee"
+    );
+    assert(
+      (await vaultBalance()) - vaultBefore == BigInt(received - fee),
+      "Vault Does Not Keep Vault Delta Minus Fee"
+    );
+  });
+
+  it("Register With Permanent Delegate Extension", async () => {
+    const tokenMint = await setup.createToken2022TestToken(
+      decimals,
+      initialBalance,
+      [splToken.ExtensionType.PermanentDelegate],
+      (mint) => [
+        splToken.createInitializePermanentDelegateInstruction(
+          mint,
+          setup.payer.publicKey,
+          tokenProgram
+        ),
+      ]
+    );
+
+    await expectTwosideError(
+      setup.registerToken(tokenMint, tokenProgram),
+      "UnsupportedMintExtension"
+    );
+  });
+
+  it("Register With Non-Transferable Extension", async () => {
+    const tokenMint = await setup.createToken2022TestToken(
+      decimals,
+      initialBalance,
+      [splToken.ExtensionType.NonTransferable],
+      (mint) => [
+        splToken.createInitializeNonTransferableMintInstruction(
+          mint,
+          tokenProgram
+        ),
+      ]
+    );
+
+    await expectTwosideError(
+      setup.registerToken(tokenMint, tokenProgram),
+      "UnsupportedMintExtension"
+    );
+  });
+
+  it("Register With Transfer Hook Authority", async () => {
+    // no hook program yet, but the authority can add one after registration
+    const tokenMint = await setup.createToken2022TestToken(
+      decimals,
+      initialBalance,
+      [splToken.ExtensionType.TransferHook],
+      (mint) => [
+        splToken.createInitializeTransferHookInstruction(
+          mint,
+          setup.payer.publicKey,
+          anchor.web3.PublicKey.default,
+          tokenProgram
+        ),
+      ]
+    );
+
+    await expectTwosideError(
+      setup.registerToken(tokenMint, tokenProgram),
+      "UnsupportedMintExtension"
+    );
+  });
+
+  it("Register With Frozen Default Account State", async () => {
+    const tokenMint = await setup.createToken2022TestToken(
+      decimals,
+      initialBalance,
+      [splToken.ExtensionType.DefaultAccountState],
+      (mint) => [
+        splToken.createInitializeDefaultAccountStateInstruction(
+          mint,
+          splToken.AccountState.Initialized,
+          tokenProgram
+        ),
+      ]
+    );
+    // switched after funding, the test balance cannot be minted to a frozen ATA
+    await splToken.updateDefaultAccountState(
+      setup.connection,
+      setup.payer,
+      tokenMint,
+      splToken.AccountState.Frozen,
+      setup.payer,
+      [],
+      undefined,
+      tokenProgram
+    );
+
+    await expectTwosideError(
+      setup.registerToken(tokenMint, tokenProgram),
+      "UnsupportedMintExtension"
+    );
+  });
+});
+
+describe("Token-2022 Derivatives", () => {
+  const decimals = 9;
+  const lockAmount = 10 * 10 ** decimals;
+
+  async function setDerivativeMetadataMode(mode: object) {
+    await setup.program.methods
+      .updateGlobalConfig({
+        ...setup.defaultGlobalConfig(),
+        derivativeMetadataMode: mode,
+      })
+      .accounts({
+        admin: setup.admin.publicKey,
+      })
+      .signers([setup.admin])
+      .rpc();
+  }
+
+  before(async () => {
+    await setDerivativeMetadataMode({ token2022: {} });
+  });
+
+  after(async () => {
+    await setDerivativeMetadataMode({ metaplex: {} });
+  });
+
+  it("Register Creates Derivative With Metadata Extension", async () => {
+    const tokenMint = await setup.createTestToken(decimals, 2 * lockAmount);
+    const { pda: derivativeMint } = setup.getDerivativeMint(tokenMint);
+    const derivativeTokenProgram = splToken.TOKEN_2022_PROGRAM_ID;
+
+    await setup.program.methods
+      .registerToken()
+      .accounts({
+        ...setup.registerAccounts(
+          tokenMint,
+          setup.user.publicKey,
+          splToken.TOKEN_PROGRAM_ID,
+          derivativeTokenProgram
+        ),
+        derivativeMetadata: null,
+        mplTokenMetadataProgram: null,
+      })
+      .signers([setup.user])
+      .rpc();
+    await setup.program.methods
+      .lock(new anchor.BN(lockAmount))
+      .accounts(
+        await setup.tokenOpAccounts(
+          tokenMint,
+          setup.user.publicKey,
+          splToken.TOKEN_PROGRAM_ID,
+          derivativeTokenProgram
+        )
+      )
+      .signers([setup.user])
+      .rpc();
+
+    const derivativeMintAccount = await splToken.getMint(
+      setup.connection,
+      derivativeMint,
+      undefined,
+      derivativeTokenProgram
+    );
+    assert(
+      derivativeMintAccount.supply ==
+        BigInt(lockAmount - setup.calculateFee(lockAmount)),
+      "Wrong Derivative Total Supply"
+    );
+
+    const metadataPointer = splToken.getMetadataPointerState(
+      derivativeMintAccount
+    );
+    assert(
+      metadataPointer.metadataAddress.toString() == derivativeMint.toString(),
+      "Metadata Pointer Does Not Point At Mint"
+    );
+
+    const derivativeMetadata = await splToken.getTokenMetadata(
+      setup.connection,
+      derivativeMint,
+      undefined,
+      derivativeTokenProgram
+    );
+    assert(
+      derivativeMetadata.name == setup.getDerivativeName("TestToken"),
+      "Wrong Derivative Name"
+    );
+    assert(
+      derivativeMetadata.symbol == setup.getDerivativeSymbol("TT"),
+      "Wrong Derivative Symbol"
+    );
+    assert(
+      derivativeMetadata.updateAuthority === undefined,
+      "Derivative Metadata Still Mutable"
+    );
+
+    const metaplexMetadata = await setup.connection.getAccountInfo(
+      setup.getTokenMetadataPDA(derivativeMint).pda
+    );
+    assert(metaplexMetadata === null, "Metaplex Metadata Created");
+
+    await setup.program.methods
+      .unlock(new anchor.BN(lockAmount / 2))
+      .accounts(
+        await setup.tokenOpAccounts(
+          tokenMint,
+          setup.user.publicKey,
+          splToken.TOKEN_PROGRAM_ID,
+          derivativeTokenProgram
+        )
+      )
+      .signers([setup.user])
+      .rpc();
+  });
+
+  it("Token-2022 Mode Requires Token-2022 Derivative", async () => {
+    const tokenMint = await setup.createTestToken(decimals, lockAmount);
+
+    await expectTwosideError(
+      setup.registerToken(tokenMint),
+      "InvalidDerivativeTokenProgram"
+    );
+  });
+});
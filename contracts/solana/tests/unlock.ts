@@ -1,5 +1,5 @@
 import { assert } from "chai";
-import { fetchLogsFromSignature, setup } from "./setup";
+import { expectTwosideError, fetchLogsFromSignature, setup } from "./setup";
 import * as anchor from "@coral-xyz/anchor";
 import { initialBalance, lockAmount, tokenDecimals, tokenIndex } from "./lock";
 import * as splToken from "@solana/spl-token";
@@ -11,15 +11,6 @@ describe("Token Unlocking", () => {
       const { pda: derivativeMintPDA, bump: derivativeMintBump } =
         setup.getDerivativeMint(tokenMint);
 
-      let founderAta = await setup.getTokenATA(
-        tokenMint,
-        setup.founder.publicKey
-      );
-      let developerAta = await setup.getTokenATA(
-        tokenMint,
-        setup.developer.publicKey
-      );
-
       let userTokenAta = await setup.getTokenATA(
         tokenMint,
         setup.user.publicKey
@@ -33,11 +24,19 @@ describe("Token Unlocking", () => {
       await setup.program.methods
         .unlock(new anchor.BN(unlockAmount))
         .accounts({
+          tokenProgram: splToken.TOKEN_PROGRAM_ID,
+          derivativeTokenProgram: splToken.TOKEN_PROGRAM_ID,
           tokenMint: tokenMint,
           signer: setup.user.publicKey,
           signerTokenAta: userTokenAta.address,
-          founderAta: founderAta.address,
-          developerAta: developerAta.address,
+          callerFeeExemption: null,
+          tokenPriceFeed: null,
+          solPriceFeed: null,
+          referrer: null,
+          referrerTokenAccount: null,
+          referrerStats: null,
+          referrerEntry: null,
+          userVolume: null,
         })
         .signers([setup.user])
         .rpc();
@@ -101,19 +100,15 @@ describe("Token Unlocking", () => {
       const feeShare =
         setup.calculateFee(lockAmount) / 2 +
         setup.calculateFee(unlockAmount) / 2;
-      developerAta = await setup.getTokenATA(
-        tokenMint,
-        setup.developer.publicKey
-      );
       assert(
-        developerAta.amount == BigInt(feeShare),
-        "Wrong Developer ATA Balance"
+        (await setup.feeBalance(tokenMint, setup.developer.publicKey)) ==
+          BigInt(feeShare),
+        "Wrong Developer Fee Balance"
       );
-
-      founderAta = await setup.getTokenATA(tokenMint, setup.founder.publicKey);
       assert(
-        founderAta.amount == BigInt(feeShare),
-        "Wrong Developer ATA Balance"
+        (await setup.feeBalance(tokenMint, setup.founder.publicKey)) ==
+          BigInt(feeShare),
+        "Wrong Founder Fee Balance"
       );
     } catch (err: any) {
       console.error("Caught error:", err);
@@ -133,4 +128,46 @@ describe("Token Unlocking", () => {
       }
     }
   });
+
+  it("Claim Fees", async () => {
+    const tokenMint = setup.tokenMint;
+    const feeShare = BigInt(
+      setup.calculateFee(lockAmount) / 2 +
+        setup.calculateFee(5 * 10 ** tokenDecimals) / 2
+    );
+
+    // recipients can claim for themselves
+    await setup.claimFees(
+      tokenMint,
+      setup.developer.publicKey,
+      undefined,
+      setup.developer
+    );
+    // or anyone can crank the payout to the canonical token account
+    await setup.claimFees(tokenMint, setup.founder.publicKey);
+
+    for (const recipient of [setup.developer, setup.founder]) {
+      const recipientAta = await setup.getTokenATA(
+        tokenMint,
+        recipient.publicKey
+      );
+      assert(recipientAta.amount == feeShare, "Wrong Claimed Amount");
+      assert(
+        (await setup.feeBalance(tokenMint, recipient.publicKey)) ==
+          BigInt(0),
+        "Fee Balance Not Cleared"
+      );
+    }
+
+    const feeVaultAta = await splToken.getAccount(
+      setup.connection,
+      setup.getFeeVault(tokenMint).ata
+    );
+    assert(feeVaultAta.amount == BigInt(0), "Fee Vault Not Emptied");
+
+    await expectTwosideError(
+      setup.claimFees(tokenMint, setup.founder.publicKey),
+      "NoFeesToClaim"
+    );
+  });
 });